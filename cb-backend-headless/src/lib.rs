@@ -0,0 +1,185 @@
+//! Renders bars into images without a display, for screenshots and tests.
+//!
+//! This works on the software adapter, or the CPU, so it doesn't need a GPU.
+
+use std::{io, path::Path};
+
+use cb_common::{BarId, Device, Gpu, OutputInfo, Target};
+
+/// The format bars are rendered in. This matches the layout of the pixels in
+/// [`Image`].
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct Headless<A> {
+  gpu: Gpu<A>,
+}
+
+/// A rendered bar.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+  /// The size of the image, in physical pixels.
+  pub width:  u32,
+  pub height: u32,
+  /// The pixels of the image, in RGBA order, one row after the other.
+  pub pixels: Vec<u8>,
+}
+
+impl<A: cb_common::App> Headless<A> {
+  pub fn new(config: A::Config) -> Self { Headless { gpu: Gpu::new_offscreen(config) } }
+
+  /// Renders on a GPU, or the software adapter. Returns `None` if there's
+  /// neither.
+  pub fn new_gpu(config: A::Config) -> Option<Self> {
+    Some(Headless { gpu: Gpu::new_offscreen_gpu(config)? })
+  }
+
+  /// Renders on the CPU, which draws the same pixels on every machine.
+  pub fn new_cpu(config: A::Config) -> Self { Headless { gpu: Gpu::new_cpu(config) } }
+
+  /// Renders the bar the app would show on `output`. `width` and `height` are
+  /// in logical pixels, so the image is `scale` times larger than that.
+  pub fn render(&mut self, output: &OutputInfo, scale: f64, width: u32, height: u32) -> Image {
+    let id = BarId::new(0);
+    let size = wgpu::Extent3d {
+      width:                 (width as f64 * scale) as u32,
+      height:                (height as f64 * scale) as u32,
+      depth_or_array_layers: 1,
+    };
+
+    let Device::Gpu(device, queue) = self.gpu.device() else {
+      return self.render_cpu(output, scale, width, height, size);
+    };
+    let (device, queue) = (device.clone(), queue.clone());
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: None,
+      size,
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+      format: FORMAT,
+      view_formats: &[],
+    });
+
+    self.gpu.add_offscreen(id, output, FORMAT, scale, width, height);
+    self.gpu.draw_offscreen(id, Target::Texture(&texture));
+    self.gpu.remove_offscreen(id);
+
+    // The GPU draws premultiplied RGBA, and images are straight RGBA.
+    let mut image = read_texture(&device, &queue, &texture);
+    for pixel in image.pixels.chunks_exact_mut(4) {
+      let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+      pixel.copy_from_slice(&[unmultiply(r, a), unmultiply(g, a), unmultiply(b, a), a]);
+    }
+    image
+  }
+
+  fn render_cpu(
+    &mut self,
+    output: &OutputInfo,
+    scale: f64,
+    width: u32,
+    height: u32,
+    size: wgpu::Extent3d,
+  ) -> Image {
+    let id = BarId::new(0);
+    let mut pixels = vec![0; size.width as usize * size.height as usize * 4];
+
+    self.gpu.add_offscreen(id, output, wgpu::TextureFormat::Bgra8Unorm, scale, width, height);
+    self.gpu.draw_offscreen(id, Target::Pixels(&mut pixels));
+    self.gpu.remove_offscreen(id);
+
+    // The CPU draws premultiplied BGRA, and images are straight RGBA.
+    for pixel in pixels.chunks_exact_mut(4) {
+      let [b, g, r, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+      pixel.copy_from_slice(&[unmultiply(r, a), unmultiply(g, a), unmultiply(b, a), a]);
+    }
+
+    Image { width: size.width, height: size.height, pixels }
+  }
+}
+
+/// Divides a premultiplied color channel `c` by `alpha`.
+fn unmultiply(c: u8, alpha: u8) -> u8 {
+  if alpha == 0 { 0 } else { (u16::from(c) * 255 / u16::from(alpha)).min(255) as u8 }
+}
+
+fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Image {
+  let (width, height) = (texture.width(), texture.height());
+
+  // Rows in the buffer have to be aligned.
+  let row_len = width * 4;
+  let padded_row_len = row_len.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+  let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+    label:              None,
+    size:               u64::from(padded_row_len * height),
+    usage:              wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+    mapped_at_creation: false,
+  });
+
+  let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+  encoder.copy_texture_to_buffer(
+    texture.as_image_copy(),
+    wgpu::TexelCopyBufferInfo {
+      buffer: &buffer,
+      layout: wgpu::TexelCopyBufferLayout {
+        offset:         0,
+        bytes_per_row:  Some(padded_row_len),
+        rows_per_image: Some(height),
+      },
+    },
+    texture.size(),
+  );
+  queue.submit(std::iter::once(encoder.finish()));
+
+  let slice = buffer.slice(..);
+  slice.map_async(wgpu::MapMode::Read, |res| res.unwrap());
+  device.poll(wgpu::PollType::Wait).unwrap();
+
+  let data = slice.get_mapped_range();
+  let pixels = data
+    .chunks(padded_row_len as usize)
+    .flat_map(|row| &row[..row_len as usize])
+    .copied()
+    .collect();
+
+  Image { width, height, pixels }
+}
+
+impl Image {
+  /// Encodes this image as a PNG into `writer`.
+  pub fn write_png(&self, writer: impl io::Write) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, self.width, self.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&self.pixels)?;
+    writer.finish()?;
+    Ok(())
+  }
+
+  /// Writes this image to the PNG file at `path`.
+  pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    self.write_png(io::BufWriter::new(file))
+  }
+
+  /// Reads the PNG file at `path`, which must be 8-bit RGBA, like the files
+  /// written by [`Image::save`].
+  pub fn load(path: impl AsRef<Path>) -> io::Result<Image> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = png::Decoder::new(io::BufReader::new(file)).read_info()?;
+    if reader.output_color_type() != (png::ColorType::Rgba, png::BitDepth::Eight) {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "image is not 8-bit RGBA"));
+    }
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+
+    Ok(Image { width: info.width, height: info.height, pixels })
+  }
+}
@@ -1,11 +1,22 @@
-use std::{collections::HashMap, os::fd::AsRawFd, ptr::NonNull};
+use std::{
+  collections::HashMap,
+  os::fd::AsRawFd,
+  ptr::NonNull,
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+};
 
-use cb_common::{BarId, Gpu};
+use cb_common::{
+  BarId, Click, Damage, Edge, Geometry, Gpu, Layer, Modifiers, MouseButton, OutputInfo, PopupKind,
+  PopupRequest, Present, Scroll,
+};
 use wayland_client::{
   Connection, Dispatch, Proxy, QueueHandle,
   protocol::{
-    wl_callback, wl_compositor, wl_display, wl_output, wl_pointer, wl_registry, wl_seat,
-    wl_shm_pool, wl_surface,
+    wl_buffer, wl_callback, wl_compositor, wl_display, wl_keyboard, wl_output, wl_pointer,
+    wl_region, wl_registry, wl_seat, wl_shm, wl_shm_pool, wl_surface,
   },
 };
 use wayland_protocols::{
@@ -13,7 +24,7 @@ use wayland_protocols::{
     fractional_scale::v1::client::{wp_fractional_scale_manager_v1, wp_fractional_scale_v1},
     viewporter::client::{wp_viewport, wp_viewporter},
   },
-  xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base},
+  xdg::shell::client::{xdg_popup, xdg_positioner, xdg_surface, xdg_toplevel, xdg_wm_base},
 };
 use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
 use wgpu::{
@@ -21,6 +32,8 @@ use wgpu::{
   rwh::{RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle},
 };
 
+mod shm;
+
 struct AppData<A> {
   gpu: Gpu<A>,
 
@@ -32,84 +45,470 @@ struct AppData<A> {
   shell:            Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
   viewporter:       Option<wp_viewporter::WpViewporter>,
   fractional_scale: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+  wm_base:          Option<xdg_wm_base::XdgWmBase>,
+  shm:              Option<wl_shm::WlShm>,
+
+  popups:     HashMap<BarId, Popup>,
+  next_popup: u32,
+
+  /// The buffers of bars drawn on the CPU.
+  buffers: HashMap<BarId, Vec<shm::Buffer>>,
 
   pointer_surface: Option<wl_surface::WlSurface>,
+  /// Axis events are collected here until the next `wl_pointer::Event::Frame`.
+  scroll:          Scroll,
+  modifiers:       Modifiers,
+  /// The last button pressed, and when, for counting double clicks.
+  last_press:      Option<(MouseButton, u32)>,
+  click_count:     u32,
 }
 
+/// The longest time between two presses of a double click, in milliseconds.
+const DOUBLE_CLICK_MS: u32 = 400;
+
 #[derive(Debug)]
 struct Monitor {
   output: wl_output::WlOutput,
-
-  surface:       Option<wl_surface::WlSurface>,
-  viewport:      Option<wp_viewport::WpViewport>,
-  layer_surface: Option<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
+  info:   OutputInfo,
+  /// Set once the first `wl_output::Event::Done` is received, so that `info`
+  /// is filled in.
+  done:   bool,
+  /// Set when the compositor closes the layer surface. The bar isn't
+  /// recreated until the output changes again, so that we don't fight with
+  /// the compositor.
+  closed: bool,
+
+  surface:          Option<wl_surface::WlSurface>,
+  viewport:         Option<wp_viewport::WpViewport>,
+  fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+  layer_surface:    Option<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
+  /// The geometry last applied to `layer_surface`.
+  geometry:         Option<Geometry>,
 
   // width/height from zwlr layer surface (ie, logical size).
   width:  u32,
   height: u32,
 }
 
+struct Popup {
+  /// The bar this popup was opened from.
+  parent: BarId,
+  kind:   PopupKind,
+
+  surface:     wl_surface::WlSurface,
+  viewport:    wp_viewport::WpViewport,
+  xdg_surface: xdg_surface::XdgSurface,
+  popup:       xdg_popup::XdgPopup,
+
+  // The logical size, from the last `xdg_popup::Event::Configure`.
+  width:  u32,
+  height: u32,
+}
+
 impl<A: cb_common::App + 'static> AppData<A> {
   fn on_change(&mut self, qh: &QueueHandle<AppData<A>>) {
+    // Remove bars on outputs that are now excluded, and bars that need a new
+    // namespace, which can only be set when creating the layer surface.
+    let stale = self
+      .monitors
+      .iter()
+      .filter(|(_, monitor)| {
+        monitor.geometry.as_ref().is_some_and(|old| match self.gpu.geometry(&monitor.info) {
+          Some(new) => new.namespace != old.namespace,
+          None => true,
+        })
+      })
+      .map(|(id, _)| *id)
+      .collect::<Vec<_>>();
+    for id in stale {
+      self.destroy_bar(id);
+    }
+
     if let Some(shell) = &self.shell
       && let Some(compositor) = &self.compositor
       && let Some(viewporter) = &self.viewporter
     {
       for (id, monitor) in &mut self.monitors {
+        if !monitor.done || monitor.closed {
+          continue;
+        }
+        let Some(geometry) = self.gpu.geometry(&monitor.info) else { continue };
+
         if monitor.surface.is_none() {
           monitor.surface = Some(compositor.create_surface(qh, *id));
           monitor.viewport =
             Some(viewporter.get_viewport(&monitor.surface.as_ref().unwrap(), qh, ()));
 
           if let Some(scale) = &self.fractional_scale {
-            scale.get_fractional_scale(monitor.surface.as_ref().unwrap(), qh, *id);
+            monitor.fractional_scale =
+              Some(scale.get_fractional_scale(monitor.surface.as_ref().unwrap(), qh, *id));
           }
         }
 
-        if monitor.layer_surface.is_none() {
+        if let Some(layer_surface) = &monitor.layer_surface {
+          if monitor.geometry.as_ref() != Some(&geometry) {
+            if layer_surface.version() >= 2 {
+              layer_surface.set_layer(layer(geometry.layer));
+            }
+            configure_layer_surface(layer_surface, &geometry);
+            monitor.surface.as_ref().unwrap().commit();
+
+            monitor.geometry = Some(geometry);
+          }
+        } else {
           let surface = monitor.surface.as_ref().unwrap();
 
           let layer_surface = shell.get_layer_surface(
             surface,
             Some(&monitor.output),
-            zwlr_layer_shell_v1::Layer::Background,
-            "foo".into(),
+            layer(geometry.layer),
+            geometry.namespace.clone(),
             qh,
             *id,
           );
 
-          layer_surface.set_size(0, 40);
-          layer_surface.set_anchor(
-            zwlr_layer_surface_v1::Anchor::Top
-              | zwlr_layer_surface_v1::Anchor::Left
-              | zwlr_layer_surface_v1::Anchor::Right,
-          );
-          layer_surface.set_margin(0, 0, 0, 0);
-          layer_surface.set_exclusive_edge(zwlr_layer_surface_v1::Anchor::Top);
-          layer_surface.set_exclusive_zone(30);
-
+          configure_layer_surface(&layer_surface, &geometry);
           surface.commit();
 
           monitor.layer_surface = Some(layer_surface);
+          monitor.geometry = Some(geometry);
+        }
+      }
+    }
+  }
+}
+
+impl<A: cb_common::App> AppData<A> {
+  /// Removes the bar on the monitor `id`, so that it can be recreated later.
+  fn destroy_bar(&mut self, id: BarId) {
+    // Popups must be destroyed before their parent.
+    let popups = self.popups.iter().filter(|(_, p)| p.parent == id).map(|(id, _)| *id);
+    for popup in popups.collect::<Vec<_>>() {
+      self.close_popup(popup);
+    }
+
+    let Some(monitor) = self.monitors.get_mut(&id) else { return };
+
+    if self.pointer_surface.is_some() && self.pointer_surface == monitor.surface {
+      self.pointer_surface = None;
+    }
+
+    // The wgpu surface must be dropped before the wayland surface.
+    self.gpu.remove_surface(id);
+    self.buffers.remove(&id);
+    monitor.destroy();
+  }
+
+  fn close_popup(&mut self, id: BarId) {
+    let Some(popup) = self.popups.remove(&id) else { return };
+
+    if self.pointer_surface.as_ref() == Some(&popup.surface) {
+      self.pointer_surface = None;
+    }
+
+    self.gpu.remove_surface(id);
+    self.buffers.remove(&id);
+    popup.popup.destroy();
+    popup.xdg_surface.destroy();
+    popup.viewport.destroy();
+    popup.surface.destroy();
+  }
+
+  /// Creates the wgpu surface for `surface`, or returns `None` if bars are
+  /// drawn on the CPU.
+  fn create_wgpu_surface(&self, surface: &wl_surface::WlSurface) -> Option<wgpu::Surface<'static>> {
+    let instance = self.gpu.instance()?;
+
+    unsafe {
+      let raw_display = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+        NonNull::new_unchecked(self.display.as_ref().unwrap().id().as_ptr() as *mut _),
+      ));
+      let raw_window = RawWindowHandle::Wayland(WaylandWindowHandle::new(NonNull::new_unchecked(
+        surface.id().as_ptr() as *mut _,
+      )));
+
+      let surface = instance
+        .create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
+          raw_display_handle: raw_display,
+          raw_window_handle:  raw_window,
+        })
+        .expect("create_surface failed");
+      Some(surface)
+    }
+  }
+}
+
+/// Presents the frames of bars and popups to their surfaces.
+struct Presenter<'a, A> {
+  monitors: &'a HashMap<BarId, Monitor>,
+  popups:   &'a HashMap<BarId, Popup>,
+  buffers:  &'a mut HashMap<BarId, Vec<shm::Buffer>>,
+  shm:      Option<&'a wl_shm::WlShm>,
+  qh:       &'a QueueHandle<AppData<A>>,
+
+  /// The buffer last returned from `buffer`, which `present` attaches.
+  drawn: Option<usize>,
+}
+
+impl<A: cb_common::App + 'static> Present for Presenter<'_, A> {
+  fn buffer(&mut self, id: BarId, width: u32, height: u32) -> Option<&mut [u8]> {
+    let buffers = self.buffers.entry(id).or_default();
+    buffers.retain(|buffer| (buffer.width, buffer.height) == (width, height));
+
+    // Usually there are two buffers, one shown by the compositor, and one to
+    // draw into. More are created if the compositor holds on to both.
+    let i = match buffers.iter().position(|buffer| !buffer.is_busy()) {
+      Some(i) => i,
+      None => {
+        let buffer = shm::Buffer::new(self.shm?, width, height, self.qh)
+          .map_err(|e| eprintln!("cannot create a shm buffer: {e}"))
+          .ok()?;
+        buffers.push(buffer);
+        buffers.len() - 1
+      }
+    };
+
+    self.drawn = Some(i);
+    Some(buffers[i].data())
+  }
+
+  fn present(&mut self, id: BarId, damage: &[Damage]) {
+    let surface = match self.monitors.get(&id) {
+      Some(monitor) => monitor.surface.as_ref(),
+      None => self.popups.get(&id).map(|popup| &popup.surface),
+    };
+    let Some(surface) = surface else { return };
+
+    // Older compositors just get the whole surface damaged.
+    if surface.version() >= 4 {
+      for d in damage {
+        surface.damage_buffer(d.x, d.y, d.width, d.height);
+      }
+    }
+    surface.frame(self.qh, id);
+
+    // Bars drawn on the GPU are committed by wgpu when they're presented.
+    if let Some(i) = self.drawn.take() {
+      self.buffers[&id][i].attach(surface);
+      if surface.version() < 4 {
+        surface.damage(0, 0, i32::MAX, i32::MAX);
+      }
+      surface.commit();
+    }
+  }
+}
+
+impl<A: cb_common::App + 'static> AppData<A> {
+  /// Closes popups the app is done with, and opens any new ones.
+  fn update_popups(&mut self, qh: &QueueHandle<Self>) {
+    let closed = self.popups.keys().copied().filter(|&id| self.gpu.popup_closed(id));
+    for id in closed.collect::<Vec<_>>() {
+      self.close_popup(id);
+    }
+
+    let bars = self.monitors.iter().filter(|(_, m)| m.layer_surface.is_some()).map(|(id, _)| *id);
+    for parent in bars.collect::<Vec<_>>() {
+      let id = BarId::popup(self.next_popup);
+      let Some(request) = self.gpu.take_popup(parent, id) else { continue };
+      self.next_popup += 1;
+
+      // Only one menu can be open at a time.
+      if request.kind == PopupKind::Menu {
+        let menus = self.popups.iter().filter(|(_, p)| p.kind == PopupKind::Menu);
+        for menu in menus.map(|(id, _)| *id).collect::<Vec<_>>() {
+          self.close_popup(menu);
+        }
+      }
+
+      self.open_popup(id, parent, request, qh);
+    }
+  }
+
+  /// Opens the popup `request` from the bar `parent`. If it can't be opened,
+  /// the app is told it was closed, so it can forget about it.
+  fn open_popup(
+    &mut self,
+    id: BarId,
+    parent: BarId,
+    request: PopupRequest,
+    qh: &QueueHandle<Self>,
+  ) {
+    if self.try_open_popup(id, parent, request, qh).is_none() {
+      self.gpu.remove_surface(id);
+    }
+  }
+
+  fn try_open_popup(
+    &mut self,
+    id: BarId,
+    parent: BarId,
+    request: PopupRequest,
+    qh: &QueueHandle<Self>,
+  ) -> Option<()> {
+    let (Some(compositor), Some(viewporter), Some(wm_base), Some(seat)) =
+      (&self.compositor, &self.viewporter, &self.wm_base, &self.seat)
+    else {
+      return None;
+    };
+    // A menu that can't grab the pointer would never close.
+    if request.kind == PopupKind::Menu && request.serial.is_none() {
+      return None;
+    }
+    let monitor = self.monitors.get(&parent)?;
+    let (Some(layer_surface), Some(geometry)) = (&monitor.layer_surface, &monitor.geometry) else {
+      return None;
+    };
+
+    use xdg_positioner::{Anchor, ConstraintAdjustment, Gravity};
+
+    // Open the popup away from the edge the bar is on.
+    let (anchor, gravity) = match geometry.edge {
+      Edge::Top => (Anchor::Bottom, Gravity::Bottom),
+      Edge::Bottom => (Anchor::Top, Gravity::Top),
+      Edge::Left => (Anchor::Right, Gravity::Right),
+      Edge::Right => (Anchor::Left, Gravity::Left),
+    };
+
+    let positioner = wm_base.create_positioner(qh, ());
+    positioner.set_size(request.width as i32, request.height as i32);
+    positioner.set_anchor_rect(
+      request.anchor_x,
+      request.anchor_y,
+      request.anchor_width,
+      request.anchor_height,
+    );
+    positioner.set_anchor(anchor);
+    positioner.set_gravity(gravity);
+    positioner.set_constraint_adjustment(
+      ConstraintAdjustment::SlideX
+        | ConstraintAdjustment::SlideY
+        | ConstraintAdjustment::FlipX
+        | ConstraintAdjustment::FlipY,
+    );
+
+    let surface = compositor.create_surface(qh, id);
+    let viewport = viewporter.get_viewport(&surface, qh, ());
+    let xdg_surface = wm_base.get_xdg_surface(&surface, qh, id);
+    let popup = xdg_surface.get_popup(None, &positioner, qh, id);
+    positioner.destroy();
+
+    layer_surface.get_popup(&popup);
+    match request.kind {
+      PopupKind::Menu => {
+        // Grabbing the pointer makes the compositor close the menu when
+        // clicking outside of it.
+        if let Some(serial) = request.serial {
+          popup.grab(seat, serial);
         }
       }
+      PopupKind::Tooltip => {
+        // Let clicks go through to whatever is under the tooltip.
+        let region = compositor.create_region(qh, ());
+        surface.set_input_region(Some(&region));
+        region.destroy();
+      }
+    }
+    surface.commit();
+
+    self.popups.insert(
+      id,
+      Popup {
+        parent,
+        kind: request.kind,
+        surface,
+        viewport,
+        xdg_surface,
+        popup,
+        width: request.width,
+        height: request.height,
+      },
+    );
+    Some(())
+  }
+}
+
+impl Monitor {
+  /// Destroys all the wayland objects of this monitor's bar. The wgpu surface
+  /// must be removed before calling this.
+  fn destroy(&mut self) {
+    if let Some(layer_surface) = self.layer_surface.take() {
+      layer_surface.destroy();
+    }
+    if let Some(scale) = self.fractional_scale.take() {
+      scale.destroy();
+    }
+    if let Some(viewport) = self.viewport.take() {
+      viewport.destroy();
     }
+    if let Some(surface) = self.surface.take() {
+      surface.destroy();
+    }
+
+    self.geometry = None;
+    self.width = 0;
+    self.height = 0;
   }
 }
 
-impl<A> Dispatch<wl_output::WlOutput, ()> for AppData<A> {
+fn configure_layer_surface(
+  layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+  geometry: &Geometry,
+) {
+  use zwlr_layer_surface_v1::Anchor;
+
+  let (edge, sides) = match geometry.edge {
+    Edge::Top => (Anchor::Top, Anchor::Left | Anchor::Right),
+    Edge::Bottom => (Anchor::Bottom, Anchor::Left | Anchor::Right),
+    Edge::Left => (Anchor::Left, Anchor::Top | Anchor::Bottom),
+    Edge::Right => (Anchor::Right, Anchor::Top | Anchor::Bottom),
+  };
+
+  match geometry.edge {
+    Edge::Top | Edge::Bottom => layer_surface.set_size(0, geometry.size),
+    Edge::Left | Edge::Right => layer_surface.set_size(geometry.size, 0),
+  }
+  layer_surface.set_anchor(edge | sides);
+  layer_surface.set_margin(
+    geometry.margin.top,
+    geometry.margin.right,
+    geometry.margin.bottom,
+    geometry.margin.left,
+  );
+  layer_surface.set_exclusive_edge(edge);
+  // The compositor adds the margin to the exclusive zone.
+  layer_surface.set_exclusive_zone(geometry.exclusive.zone(geometry.size) as i32);
+}
+
+fn layer(layer: Layer) -> zwlr_layer_shell_v1::Layer {
+  match layer {
+    Layer::Background => zwlr_layer_shell_v1::Layer::Background,
+    Layer::Bottom => zwlr_layer_shell_v1::Layer::Bottom,
+    Layer::Top => zwlr_layer_shell_v1::Layer::Top,
+    Layer::Overlay => zwlr_layer_shell_v1::Layer::Overlay,
+  }
+}
+
+impl<A: cb_common::App + 'static> Dispatch<wl_output::WlOutput, ()> for AppData<A> {
   fn event(
     state: &mut Self,
     output: &wl_output::WlOutput,
     event: wl_output::Event,
     _: &(),
     _: &Connection,
-    _: &QueueHandle<Self>,
+    qh: &QueueHandle<Self>,
   ) {
-    let _monitor = state.monitors.values_mut().find(|m| &m.output == output).unwrap();
+    let Some(monitor) = state.monitors.values_mut().find(|m| &m.output == output) else { return };
     match event {
+      wl_output::Event::Geometry { make, model, .. } => {
+        monitor.info.make = make;
+        monitor.info.model = model;
+      }
+      wl_output::Event::Name { name } => monitor.info.name = name,
+      wl_output::Event::Description { description } => monitor.info.description = description,
       wl_output::Event::Done => {
-        println!("monitors: {:?}", state.monitors);
+        monitor.done = true;
+        monitor.closed = false;
+        state.on_change(qh);
       }
       _ => {}
     }
@@ -129,6 +528,33 @@ impl<A> Dispatch<wl_shm_pool::WlShmPool, ()> for AppData<A> {
   }
 }
 
+impl<A> Dispatch<wl_shm::WlShm, ()> for AppData<A> {
+  fn event(
+    _: &mut Self,
+    _: &wl_shm::WlShm,
+    _: wl_shm::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl<A> Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>> for AppData<A> {
+  fn event(
+    _: &mut Self,
+    _: &wl_buffer::WlBuffer,
+    event: wl_buffer::Event,
+    busy: &Arc<AtomicBool>,
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    if let wl_buffer::Event::Release = event {
+      busy.store(false, Ordering::Release);
+    }
+  }
+}
+
 impl<A> Dispatch<wl_compositor::WlCompositor, ()> for AppData<A> {
   fn event(
     _state: &mut Self,
@@ -160,7 +586,7 @@ impl<A: cb_common::App> Dispatch<wl_surface::WlSurface, BarId> for AppData<A> {
       wl_surface::Event::PreferredBufferScale { factor } => {
         let Some(monitor) = state.monitors.get_mut(id) else { return };
 
-        let bar = state.gpu.bar_mut(*id).unwrap();
+        let Some(bar) = state.gpu.bar_mut(*id) else { return };
         if bar.scale != factor as f64 {
           bar.scale = factor as f64;
           state.gpu.set_size(*id, factor as f64, monitor.width, monitor.height);
@@ -180,26 +606,95 @@ impl<A: cb_common::App> Dispatch<wl_surface::WlSurface, BarId> for AppData<A> {
 impl<A> Dispatch<xdg_wm_base::XdgWmBase, ()> for AppData<A> {
   fn event(
     _state: &mut Self,
-    _surface: &xdg_wm_base::XdgWmBase,
+    wm_base: &xdg_wm_base::XdgWmBase,
     event: xdg_wm_base::Event,
     _: &(),
     _: &Connection,
     _: &QueueHandle<Self>,
   ) {
-    println!("wm_base event: {:?}", event);
+    match event {
+      xdg_wm_base::Event::Ping { serial } => wm_base.pong(serial),
+      _ => println!("wm_base event: {:?}", event),
+    }
   }
 }
 
-impl<A> Dispatch<xdg_surface::XdgSurface, ()> for AppData<A> {
+impl<A> Dispatch<wl_region::WlRegion, ()> for AppData<A> {
   fn event(
-    _state: &mut Self,
-    _surface: &xdg_surface::XdgSurface,
-    event: xdg_surface::Event,
+    _: &mut Self,
+    _: &wl_region::WlRegion,
+    _: wl_region::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl<A> Dispatch<xdg_positioner::XdgPositioner, ()> for AppData<A> {
+  fn event(
+    _: &mut Self,
+    _: &xdg_positioner::XdgPositioner,
+    _: xdg_positioner::Event,
     _: &(),
     _: &Connection,
     _: &QueueHandle<Self>,
   ) {
-    println!("xdg_surface event: {:?}", event);
+  }
+}
+
+impl<A: cb_common::App> Dispatch<xdg_surface::XdgSurface, BarId> for AppData<A> {
+  fn event(
+    state: &mut Self,
+    xdg_surface: &xdg_surface::XdgSurface,
+    event: xdg_surface::Event,
+    id: &BarId,
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    match event {
+      xdg_surface::Event::Configure { serial } => {
+        xdg_surface.ack_configure(serial);
+
+        let Some(popup) = state.popups.get(id) else { return };
+        let (width, height) = (popup.width, popup.height);
+        popup.viewport.set_destination(width as i32, height as i32);
+
+        // Popups use the same scale as their bar.
+        let scale = state.gpu.bar(popup.parent).map_or(1.0, |bar| bar.scale);
+
+        if state.gpu.bar(*id).is_none() {
+          let surface = state.create_wgpu_surface(&popup.surface);
+          state.gpu.add_popup_surface(*id, surface, scale, width, height);
+        }
+        state.gpu.set_size(*id, scale, width, height);
+        state.gpu.render_bar(*id);
+      }
+      _ => println!("xdg_surface event: {:?}", event),
+    }
+  }
+}
+
+impl<A: cb_common::App> Dispatch<xdg_popup::XdgPopup, BarId> for AppData<A> {
+  fn event(
+    state: &mut Self,
+    _: &xdg_popup::XdgPopup,
+    event: xdg_popup::Event,
+    id: &BarId,
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    match event {
+      xdg_popup::Event::Configure { width, height, .. } => {
+        if let Some(popup) = state.popups.get_mut(id) {
+          popup.width = width as u32;
+          popup.height = height as u32;
+        }
+      }
+      // Sent when clicking outside the popup.
+      xdg_popup::Event::PopupDone => state.close_popup(*id),
+      _ => {}
+    }
   }
 }
 
@@ -240,33 +735,40 @@ impl<A: cb_common::App> Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, BarI
   ) {
     match event {
       zwlr_layer_surface_v1::Event::Configure { serial, width, height } => {
-        if let Some(monitor) = state.monitors.get_mut(id) {
-          monitor.layer_surface.as_ref().unwrap().ack_configure(serial);
+        if let Some(monitor) = state.monitors.get_mut(id)
+          && let Some(layer_surface) = &monitor.layer_surface
+        {
+          layer_surface.ack_configure(serial);
           monitor.width = width;
           monitor.height = height;
 
-          unsafe {
-            let raw_display = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
-              NonNull::new_unchecked(state.display.as_mut().unwrap().id().as_ptr() as *mut _),
-            ));
-            let raw_window = RawWindowHandle::Wayland(WaylandWindowHandle::new(
-              NonNull::new_unchecked(monitor.surface.as_mut().unwrap().id().as_ptr() as *mut _),
-            ));
-
-            let surface = state
-              .gpu
-              .instance()
-              .create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
-                raw_display_handle: raw_display,
-                raw_window_handle:  raw_window,
-              })
-              .expect("create_surface failed");
-            state.gpu.add_surface(*id, surface, 1.0, width, height);
+          // The geometry changed, so resize the existing bar.
+          if let Some(bar) = state.gpu.bar(*id) {
+            let scale = bar.scale;
+            state.gpu.set_size(*id, scale, width, height);
+
+            if state.fractional_scale.is_some()
+              && let Some(viewport) = &monitor.viewport
+            {
+              viewport.set_destination(width as i32, height as i32);
+            }
             state.gpu.render_bar(*id);
+            return;
           }
 
-          // TODO: Request a new frame with this:
-          // monitor.surface.as_ref().unwrap().frame(qh, *id);
+          let info = monitor.info.clone();
+          let surface = monitor.surface.clone().unwrap();
+          let surface = state.create_wgpu_surface(&surface);
+          state.gpu.add_surface(*id, &info, surface, 1.0, width, height);
+          state.gpu.render_bar(*id);
+        }
+      }
+      // The compositor is done with this surface, usually because the output
+      // is being turned off or removed.
+      zwlr_layer_surface_v1::Event::Closed => {
+        state.destroy_bar(*id);
+        if let Some(monitor) = state.monitors.get_mut(id) {
+          monitor.closed = true;
         }
       }
       _ => {}
@@ -283,8 +785,7 @@ impl<A: cb_common::App> Dispatch<wl_callback::WlCallback, BarId> for AppData<A>
     _: &Connection,
     _: &QueueHandle<Self>,
   ) {
-    println!("drawing!!");
-    state.gpu.render_bar(*id);
+    state.gpu.frame_done(*id);
   }
 }
 
@@ -301,6 +802,33 @@ impl<A> Dispatch<wl_seat::WlSeat, ()> for AppData<A> {
   }
 }
 
+impl<A> Dispatch<wl_keyboard::WlKeyboard, ()> for AppData<A> {
+  fn event(
+    state: &mut Self,
+    _: &wl_keyboard::WlKeyboard,
+    event: wl_keyboard::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    match event {
+      // These are the modifier indices of the standard xkb keymaps. Anything
+      // else would need the keymap parsed, which isn't worth it just for this.
+      wl_keyboard::Event::Modifiers { mods_depressed, mods_latched, mods_locked, .. } => {
+        let mods = mods_depressed | mods_latched | mods_locked;
+        state.modifiers = Modifiers {
+          shift: mods & (1 << 0) != 0,
+          ctrl:  mods & (1 << 2) != 0,
+          alt:   mods & (1 << 3) != 0,
+          logo:  mods & (1 << 6) != 0,
+        };
+      }
+      wl_keyboard::Event::Leave { .. } => state.modifiers = Modifiers::default(),
+      _ => {}
+    }
+  }
+}
+
 impl<A> Dispatch<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, ()> for AppData<A> {
   fn event(
     _: &mut Self,
@@ -343,7 +871,7 @@ impl<A: cb_common::App> Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, Ba
         let Some(monitor) = state.monitors.get_mut(id) else { return };
         let Some(viewport) = monitor.viewport.as_ref() else { return };
 
-        let bar = state.gpu.bar_mut(*id).unwrap();
+        let Some(bar) = state.gpu.bar_mut(*id) else { return };
         if bar.scale != scale {
           bar.scale = scale;
           state.gpu.set_size(*id, scale, monitor.width, monitor.height);
@@ -374,7 +902,7 @@ impl<A: cb_common::App> Dispatch<wp_viewport::WpViewport, ()> for AppData<A> {
 impl<A: cb_common::App> Dispatch<wl_pointer::WlPointer, ()> for AppData<A> {
   fn event(
     state: &mut Self,
-    _: &wl_pointer::WlPointer,
+    pointer: &wl_pointer::WlPointer,
     event: wl_pointer::Event,
     _: &(),
     _: &Connection,
@@ -395,11 +923,22 @@ impl<A: cb_common::App> Dispatch<wl_pointer::WlPointer, ()> for AppData<A> {
         state.pointer_surface = None;
       }
 
-      wl_pointer::Event::Button { state: s, .. } => {
-        if matches!(s.into_result(), Ok(wl_pointer::ButtonState::Pressed)) {
-          if let Some(bar) = state.pointer_bar() {
-            state.gpu.click_mouse(bar);
-          }
+      wl_pointer::Event::Button { serial, time, button, state: s, .. } => {
+        let button = MouseButton::from_code(button);
+        let pressed = matches!(s.into_result(), Ok(wl_pointer::ButtonState::Pressed));
+
+        if pressed {
+          let repeat = state.last_press.is_some_and(|(last, last_time)| {
+            last == button && time.wrapping_sub(last_time) <= DOUBLE_CLICK_MS
+          });
+          state.click_count = if repeat { state.click_count + 1 } else { 1 };
+          state.last_press = Some((button, time));
+        }
+
+        if let Some(bar) = state.pointer_bar() {
+          let modifiers = state.modifiers;
+          let click = Click { button, pressed, modifiers, count: state.click_count, serial };
+          state.gpu.click_mouse(bar, click);
         }
       }
 
@@ -409,12 +948,50 @@ impl<A: cb_common::App> Dispatch<wl_pointer::WlPointer, ()> for AppData<A> {
         }
       }
 
+      wl_pointer::Event::Axis { axis, value, .. } => {
+        match axis.into_result() {
+          Ok(wl_pointer::Axis::HorizontalScroll) => state.scroll.dx += value,
+          Ok(wl_pointer::Axis::VerticalScroll) => state.scroll.dy += value,
+          _ => {}
+        }
+
+        // Before version 5, there are no frame events, so send each axis event
+        // on its own.
+        if pointer.version() < 5 {
+          state.flush_scroll();
+        }
+      }
+      // Only sent before version 8.
+      wl_pointer::Event::AxisDiscrete { axis, discrete } => match axis.into_result() {
+        Ok(wl_pointer::Axis::HorizontalScroll) => state.scroll.steps_x += f64::from(discrete),
+        Ok(wl_pointer::Axis::VerticalScroll) => state.scroll.steps_y += f64::from(discrete),
+        _ => {}
+      },
+      // Replaces `AxisDiscrete` in version 8. A value of 120 is one wheel click.
+      wl_pointer::Event::AxisValue120 { axis, value120 } => match axis.into_result() {
+        Ok(wl_pointer::Axis::HorizontalScroll) => {
+          state.scroll.steps_x += f64::from(value120) / 120.0
+        }
+        Ok(wl_pointer::Axis::VerticalScroll) => state.scroll.steps_y += f64::from(value120) / 120.0,
+        _ => {}
+      },
+      wl_pointer::Event::Frame => state.flush_scroll(),
+
       _ => {}
     }
   }
 }
 
 impl<A: cb_common::App> AppData<A> {
+  fn flush_scroll(&mut self) {
+    let scroll = std::mem::take(&mut self.scroll);
+    if !scroll.is_empty()
+      && let Some(bar) = self.pointer_bar()
+    {
+      self.gpu.scroll_mouse(bar, scroll);
+    }
+  }
+
   fn pointer_bar(&mut self) -> Option<BarId> {
     let surface = self.pointer_surface.as_ref()?;
 
@@ -423,6 +1000,11 @@ impl<A: cb_common::App> AppData<A> {
         return Some(*id);
       }
     }
+    for (id, popup) in &self.popups {
+      if &popup.surface == surface {
+        return Some(*id);
+      }
+    }
 
     None
   }
@@ -437,6 +1019,18 @@ impl<A: cb_common::App + 'static> Dispatch<wl_registry::WlRegistry, ()> for AppD
     _: &Connection,
     qh: &QueueHandle<Self>,
   ) {
+    if let wl_registry::Event::GlobalRemove { name } = event {
+      let id = BarId::new(name);
+      if state.monitors.contains_key(&id) {
+        state.destroy_bar(id);
+
+        let monitor = state.monitors.remove(&id).unwrap();
+        if monitor.output.version() >= 3 {
+          monitor.output.release();
+        }
+      }
+    }
+
     if let wl_registry::Event::Global { name, interface, version } = event {
       if interface == wl_output::WlOutput::interface().name {
         let id = BarId::new(name);
@@ -444,12 +1038,17 @@ impl<A: cb_common::App + 'static> Dispatch<wl_registry::WlRegistry, ()> for AppD
         state.monitors.insert(
           id,
           Monitor {
-            output:        registry.bind(name, version, qh, ()),
-            surface:       None,
-            viewport:      None,
-            layer_surface: None,
-            width:         0,
-            height:        0,
+            output:           registry.bind(name, version, qh, ()),
+            info:             OutputInfo::default(),
+            done:             false,
+            closed:           false,
+            surface:          None,
+            viewport:         None,
+            fractional_scale: None,
+            layer_surface:    None,
+            geometry:         None,
+            width:            0,
+            height:           0,
           },
         );
       } else if interface == wl_compositor::WlCompositor::interface().name {
@@ -457,6 +1056,7 @@ impl<A: cb_common::App + 'static> Dispatch<wl_registry::WlRegistry, ()> for AppD
       } else if interface == wl_seat::WlSeat::interface().name {
         state.seat = Some(registry.bind(name, version, qh, ()));
         state.seat.as_ref().unwrap().get_pointer(qh, ());
+        state.seat.as_ref().unwrap().get_keyboard(qh, ());
       } else if interface == zwlr_layer_shell_v1::ZwlrLayerShellV1::interface().name {
         state.shell = Some(registry.bind(name, version, qh, ()));
       } else if interface == wp_viewporter::WpViewporter::interface().name {
@@ -465,6 +1065,10 @@ impl<A: cb_common::App + 'static> Dispatch<wl_registry::WlRegistry, ()> for AppD
         == wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1::interface().name
       {
         state.fractional_scale = Some(registry.bind(name, version, qh, ()));
+      } else if interface == xdg_wm_base::XdgWmBase::interface().name {
+        state.wm_base = Some(registry.bind(name, version, qh, ()));
+      } else if interface == wl_shm::WlShm::interface().name {
+        state.shm = Some(registry.bind(name, version, qh, ()));
       }
 
       state.on_change(qh);
@@ -489,49 +1093,86 @@ pub fn setup<A: cb_common::App + 'static>(config: A::Config) {
     seat:             None,
     viewporter:       None,
     fractional_scale: None,
+    wm_base:          None,
+    shm:              None,
+    popups:           HashMap::new(),
+    next_popup:       0,
+    buffers:          HashMap::new(),
     display:          None,
     pointer_surface:  None,
+    scroll:           Scroll::default(),
+    modifiers:        Modifiers::default(),
+    last_press:       None,
+    click_count:      0,
   };
   app.display = Some(display);
 
   loop {
     event_queue.dispatch_pending(&mut app).unwrap();
 
+    if app.gpu.update() {
+      app.on_change(&qh);
+    }
+    app.update_popups(&qh);
+
     if app.gpu.needs_render() {
-      app.gpu.render();
+      // Hidden bars never get their frame callback, so they stop rendering
+      // until they are visible again.
+      app.gpu.render(&mut Presenter {
+        monitors: &app.monitors,
+        popups:   &app.popups,
+        buffers:  &mut app.buffers,
+        shm:      app.shm.as_ref(),
+        qh:       &qh,
+        drawn:    None,
+      });
     }
 
     event_queue.flush().unwrap();
 
     if let Some(guard) = event_queue.prepare_read() {
-      blocking_read(guard, app.gpu.waker.as_deref(), Some(std::time::Duration::from_secs(1)))
-        .unwrap();
+      // Sleep until a bar needs to be drawn again, or something wakes us up.
+      let now = std::time::Instant::now();
+      let timeout = app.gpu.deadline().map(|deadline| deadline.saturating_duration_since(now));
+      blocking_read(guard, app.gpu.waker.as_deref(), app.gpu.events.as_deref(), timeout).unwrap();
     }
   }
 }
 
 // Copied from `wayland-client::conn::blocking_read`, so we can pass in a
-// timeout.
+// timeout, and poll the event sources of the app.
 fn blocking_read(
   guard: wayland_backend::client::ReadEventsGuard,
   waker: Option<&cb_common::Waker>,
+  events: Option<&cb_common::EventLoop>,
   timeout: Option<std::time::Duration>,
 ) -> Result<usize, wayland_backend::client::WaylandError> {
-  let mut fds: [libc::pollfd; 2] = unsafe { std::mem::zeroed() };
-
-  fds[0].fd = guard.connection_fd().as_raw_fd();
-  fds[0].events = libc::POLLIN | libc::POLLERR;
-  let mut n_fds = 1;
-
-  if let Some(waker) = waker {
-    fds[1].fd = waker.fd().as_raw_fd();
-    fds[1].events = libc::POLLIN | libc::POLLERR;
-    n_fds = 2;
+  // The connection and the waker always come first, and the event sources
+  // after them.
+  let mut fds = vec![
+    libc::pollfd {
+      fd:      guard.connection_fd().as_raw_fd(),
+      events:  libc::POLLIN | libc::POLLERR,
+      revents: 0,
+    },
+    libc::pollfd {
+      fd:      waker.map(|w| w.fd().as_raw_fd()).unwrap_or(-1),
+      events:  libc::POLLIN | libc::POLLERR,
+      revents: 0,
+    },
+  ];
+  if let Some(events) = events {
+    events.fill_pollfds(&mut fds);
   }
 
   loop {
     let ret = unsafe {
-      libc::poll(&mut fds as *mut _, n_fds, timeout.map(|t| t.as_millis() as _).unwrap_or(-1))
+      // Round up, so that we don't wake up just before the deadline. Deadlines
+      // too far away to fit are cut short, and the loop waits again after.
+      let timeout = timeout
+        .map(|t| t.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128) as libc::c_int)
+        .unwrap_or(-1);
+      libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout)
     };
 
     if ret == -1 {
@@ -548,7 +1189,7 @@ fn blocking_read(
   let mut res = Ok(0);
 
   let mut guard = Some(guard);
-  for (i, fd) in fds.iter().enumerate() {
+  for (i, fd) in fds[..2].iter().enumerate() {
     if fd.revents == 0 {
       continue;
     }
@@ -573,5 +1214,10 @@ fn blocking_read(
     }
   }
 
+  // Timers are dispatched here too, so this must run even if no fds are ready.
+  if let Some(events) = events {
+    events.dispatch(&fds[2..]);
+  }
+
   res
 }
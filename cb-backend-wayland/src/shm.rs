@@ -0,0 +1,112 @@
+//! Buffers in shared memory, which bars drawn on the CPU are presented with.
+
+use std::{
+  io,
+  os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+  ptr::NonNull,
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+};
+
+use wayland_client::{
+  Dispatch, QueueHandle,
+  protocol::{wl_buffer, wl_shm, wl_shm_pool, wl_surface},
+};
+
+/// A `wl_buffer` in the format of `Target::Pixels`.
+pub struct Buffer {
+  buffer: wl_buffer::WlBuffer,
+  data:   NonNull<u8>,
+  len:    usize,
+
+  pub width:  u32,
+  pub height: u32,
+
+  /// Set while the compositor is reading from this buffer. This is cleared by
+  /// `wl_buffer::Event::Release`.
+  busy: Arc<AtomicBool>,
+}
+
+impl Buffer {
+  pub fn new<D>(
+    shm: &wl_shm::WlShm,
+    width: u32,
+    height: u32,
+    qh: &QueueHandle<D>,
+  ) -> io::Result<Self>
+  where
+    D: Dispatch<wl_shm_pool::WlShmPool, ()>
+      + Dispatch<wl_buffer::WlBuffer, Arc<AtomicBool>>
+      + 'static,
+  {
+    let stride = width as usize * 4;
+    let len = stride * height as usize;
+
+    let fd = unsafe {
+      let fd = libc::memfd_create(c"correct-bar".as_ptr(), libc::MFD_CLOEXEC);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      OwnedFd::from_raw_fd(fd)
+    };
+
+    let data = unsafe {
+      if libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      let data = libc::mmap(
+        std::ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd.as_raw_fd(),
+        0,
+      );
+      if data == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+      }
+      NonNull::new_unchecked(data.cast())
+    };
+
+    let busy = Arc::new(AtomicBool::new(false));
+    let pool = shm.create_pool(fd.as_fd(), len as i32, qh, ());
+    let buffer = pool.create_buffer(
+      0,
+      width as i32,
+      height as i32,
+      stride as i32,
+      wl_shm::Format::Argb8888,
+      qh,
+      busy.clone(),
+    );
+    // The buffer keeps the memory alive on the compositor's side.
+    pool.destroy();
+
+    Ok(Buffer { buffer, data, len, width, height, busy })
+  }
+
+  pub fn is_busy(&self) -> bool { self.busy.load(Ordering::Acquire) }
+
+  pub fn data(&mut self) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+  }
+
+  /// Attaches this buffer to `surface`. It can't be drawn into again until the
+  /// compositor releases it.
+  pub fn attach(&self, surface: &wl_surface::WlSurface) {
+    surface.attach(Some(&self.buffer), 0, 0);
+    self.busy.store(true, Ordering::Release);
+  }
+}
+
+impl Drop for Buffer {
+  fn drop(&mut self) {
+    self.buffer.destroy();
+    unsafe {
+      libc::munmap(self.data.as_ptr().cast(), self.len);
+    }
+  }
+}
@@ -35,6 +35,8 @@ struct State {
   running:   Option<Instant>,
   direction: Direction,
   time:      f64,
+  /// Set to turn around at either end, instead of stopping.
+  repeat:    bool,
 }
 
 #[derive(Clone, Copy, Default, PartialEq)]
@@ -59,6 +61,7 @@ impl Animation {
   ease!(CubicInOut, ease_in_out);
 
   pub fn is_running(&self) -> bool { self.state.borrow().running.is_some() }
+  pub fn is_repeating(&self) -> bool { self.state.borrow().repeat }
 
   pub fn interpolate(&self, start: f64, end: f64) -> f64 {
     let t = self.state.borrow().time / self.duration;
@@ -85,6 +88,23 @@ impl Animation {
     state.direction = Direction::Reverse;
   }
 
+  /// Runs back and forth until `stop` is called.
+  pub fn repeat(&mut self) {
+    let state = self.state.get_mut();
+    state.repeat = true;
+    if state.running.is_none() {
+      state.running = Some(Instant::now());
+      state.direction =
+        if state.time >= self.duration { Direction::Reverse } else { Direction::Forward };
+    }
+  }
+
+  /// Stops repeating, and runs back to the start.
+  pub fn stop(&mut self) {
+    self.state.get_mut().repeat = false;
+    self.run(false);
+  }
+
   pub fn advance(&self, now: std::time::Instant) {
     let mut state = self.state.borrow_mut();
 
@@ -98,7 +118,11 @@ impl Animation {
 
         if state.time >= self.duration {
           state.time = self.duration;
-          state.running = None;
+          if state.repeat {
+            state.direction = Direction::Reverse;
+          } else {
+            state.running = None;
+          }
         }
       }
       Direction::Reverse => {
@@ -106,7 +130,11 @@ impl Animation {
 
         if state.time <= 0.0 {
           state.time = 0.0;
-          state.running = None;
+          if state.repeat {
+            state.direction = Direction::Forward;
+          } else {
+            state.running = None;
+          }
         }
       }
     }
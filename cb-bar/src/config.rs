@@ -0,0 +1,674 @@
+//! Loads bars from a config file at runtime.
+//!
+//! The config file is a TOML file, with a list of modules for each side of
+//! the bar:
+//!
+//! ```toml
+//! [[left]]
+//! module = "hypr"
+//! primary = "oklch(0.7 0.2 310)"
+//! secondary = "oklab(0.5 0 0)"
+//!
+//! [[right]]
+//! module = "clock"
+//! primary = "white"
+//! secondary = "oklab(0.5 0 0)"
+//! ```
+//!
+//! The `module` key selects a constructor from the [`Registry`], and every
+//! other key is deserialized into that module's spec.
+//!
+//! The placement of the bar is set with top-level keys, which all have
+//! defaults:
+//!
+//! ```toml
+//! edge = "top"          # or "bottom", "left", "right"
+//! size = 40             # the height of the bar, or width if it's vertical
+//! exclusive = 30        # keep windows this far from the edge, or `true` for
+//!                       # the whole bar, or `false` to let them cover it
+//! layer = "background"  # or "bottom", "top", "overlay"
+//! margin = { top = 5, left = 5, right = 5 }
+//! namespace = "correct-bar"
+//! ```
+//!
+//! Outputs can be given their own bar with an `[[output]]` section. The first
+//! section where every given key (`name`, `description`, `make` and `model`)
+//! matches the output is used, and outputs that don't match any section get
+//! the top-level bar. `[[output]]` sections may also override any of the
+//! placement keys:
+//!
+//! ```toml
+//! [[output]]
+//! name = "eDP-1"
+//!
+//! [[output.right]]
+//! module = "clock"
+//! primary = "white"
+//! secondary = "oklab(0.5 0 0)"
+//!
+//! # Don't show a bar on this output at all.
+//! [[output]]
+//! make = "LG Electronics"
+//! exclude = true
+//! ```
+
+use std::{
+  collections::HashMap,
+  fmt,
+  ops::Range,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use cb_core::{Color, Edge, Exclusive, Geometry, Layer, Margin, OutputInfo, Render};
+use serde::{Deserialize, Deserializer, de::DeserializeOwned};
+
+use crate::{Bar, Layout, Module, TextLayout};
+
+/// Maps module names in the config file to the spec they are deserialized
+/// into.
+#[derive(Default)]
+pub struct Registry {
+  entries: HashMap<&'static str, Entry>,
+}
+
+struct Entry {
+  validate: fn(toml::Table) -> Result<(), toml::de::Error>,
+  build:    fn(toml::Table) -> Box<dyn Module>,
+}
+
+pub struct Config {
+  pub registry: Registry,
+
+  /// The config file to load.
+  pub path: PathBuf,
+
+  /// The config to use if `path` doesn't exist.
+  pub default: &'static str,
+}
+
+/// The parsed contents of a config file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSpec {
+  /// The bar for outputs that don't match any of `outputs`.
+  pub bar:     BarSpec,
+  pub outputs: Vec<OutputSpec>,
+}
+
+/// An `[[output]]` section. Each key that is set must match the output for
+/// this section to apply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OutputSpec {
+  pub name:        Option<String>,
+  pub description: Option<String>,
+  pub make:        Option<String>,
+  pub model:       Option<String>,
+
+  /// Don't show a bar on matching outputs.
+  pub exclude: bool,
+  pub bar:     BarSpec,
+}
+
+/// The geometry and modules of a single bar.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BarSpec {
+  pub geometry: Geometry,
+
+  pub left:   Vec<ModuleSpec>,
+  pub center: Vec<ModuleSpec>,
+  pub right:  Vec<ModuleSpec>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModuleSpec {
+  /// The `module` key.
+  pub name:    String,
+  /// Every other key in the module's table.
+  pub options: toml::Table,
+
+  pos: Position,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Position {
+  line:   usize,
+  column: usize,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+  path:    PathBuf,
+  pos:     Option<Position>,
+  message: String,
+}
+
+type RawModules = Vec<toml::Spanned<toml::Table>>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+  edge:      Option<Edge>,
+  size:      Option<u32>,
+  exclusive: Option<Exclusive>,
+  layer:     Option<Layer>,
+  margin:    Option<Margin>,
+  namespace: Option<String>,
+
+  #[serde(default)]
+  left:   RawModules,
+  #[serde(default)]
+  center: RawModules,
+  #[serde(default)]
+  right:  RawModules,
+  #[serde(default)]
+  output: Vec<RawOutput>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawOutput {
+  name:        Option<String>,
+  description: Option<String>,
+  make:        Option<String>,
+  model:       Option<String>,
+  #[serde(default)]
+  exclude:     bool,
+
+  edge:      Option<Edge>,
+  size:      Option<u32>,
+  exclusive: Option<Exclusive>,
+  layer:     Option<Layer>,
+  margin:    Option<Margin>,
+  namespace: Option<String>,
+
+  #[serde(default)]
+  left:   RawModules,
+  #[serde(default)]
+  center: RawModules,
+  #[serde(default)]
+  right:  RawModules,
+}
+
+/// Geometry keys that override the defaults, or the top-level keys for
+/// `[[output]]` sections.
+struct RawGeometry {
+  edge:      Option<Edge>,
+  size:      Option<u32>,
+  exclusive: Option<Exclusive>,
+  layer:     Option<Layer>,
+  margin:    Option<Margin>,
+  namespace: Option<String>,
+}
+
+impl Registry {
+  pub fn new() -> Self { Registry::default() }
+
+  /// Registers the spec `T` under `name`. The options of every module named
+  /// `name` in the config file are deserialized into a `T`.
+  pub fn register<T>(&mut self, name: &'static str)
+  where
+    T: DeserializeOwned + Into<Box<dyn Module>>,
+  {
+    self.entries.insert(
+      name,
+      Entry {
+        validate: |options| options.try_into::<T>().map(drop),
+        build:    |options| options.try_into::<T>().expect("options should be validated").into(),
+      },
+    );
+  }
+
+  fn validate(&self, spec: &ModuleSpec) -> Result<(), String> {
+    let Some(entry) = self.entries.get(spec.name.as_str()) else {
+      return Err(format!("unknown module `{}`", spec.name));
+    };
+
+    (entry.validate)(spec.options.clone())
+      .map_err(|e| format!("module `{}`: {}", spec.name, e.message()))
+  }
+
+  fn build(&self, spec: &ModuleSpec) -> Box<dyn Module> {
+    (self.entries[spec.name.as_str()].build)(spec.options.clone())
+  }
+}
+
+impl Config {
+  /// Creates a config that loads from
+  /// `$XDG_CONFIG_HOME/correct-bar/config.toml`, and falls back to `default`
+  /// if that file doesn't exist.
+  pub fn new(registry: Registry, default: &'static str) -> Self {
+    Config { registry, path: default_path(), default }
+  }
+
+  /// Parses the config file, and checks that every module in it can be built.
+  pub fn load(&self) -> Result<ConfigSpec, ConfigError> {
+    let (path, source) = match std::fs::read_to_string(&self.path) {
+      Ok(s) => (self.path.as_path(), s),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        (Path::new("<default>"), self.default.to_string())
+      }
+      Err(e) => {
+        return Err(ConfigError {
+          path:    self.path.clone(),
+          pos:     None,
+          message: e.to_string(),
+        });
+      }
+    };
+
+    let spec = ConfigSpec::parse(path, &source)?;
+
+    let bars = std::iter::once(&spec.bar).chain(spec.outputs.iter().map(|o| &o.bar));
+    for module in bars.flat_map(BarSpec::modules) {
+      self.registry.validate(module).map_err(|message| ConfigError {
+        path: path.to_path_buf(),
+        pos: Some(module.pos),
+        message,
+      })?;
+    }
+
+    Ok(spec)
+  }
+
+  /// Loads the config file, and builds the bar for `output`, along with its
+  /// geometry. Returns `None` if `output` doesn't get a bar.
+  pub fn build_bar(&self, output: &OutputInfo) -> Result<Option<(Bar, Geometry)>, ConfigError> {
+    let spec = self.load()?;
+    let Some(bar) = spec.bar_for(output) else { return Ok(None) };
+
+    let build = |specs: &[ModuleSpec]| specs.iter().map(|spec| self.build_module(spec)).collect();
+    Ok(Some((
+      Bar {
+        left_modules:   build(&bar.left),
+        center_modules: build(&bar.center),
+        right_modules:  build(&bar.right),
+      },
+      bar.geometry.clone(),
+    )))
+  }
+
+  /// Builds a module from a spec returned by [`Config::load`].
+  pub(crate) fn build_module(&self, spec: &ModuleSpec) -> Box<dyn Module> {
+    self.registry.build(spec)
+  }
+}
+
+fn default_path() -> PathBuf {
+  let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+    _ => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config"),
+  };
+
+  config_home.join("correct-bar").join("config.toml")
+}
+
+impl ConfigSpec {
+  pub fn parse(path: &Path, source: &str) -> Result<ConfigSpec, ConfigError> {
+    let error = |span: Option<Range<usize>>, message: &str| ConfigError {
+      path:    path.to_path_buf(),
+      pos:     span.map(|span| Position::from_offset(source, span.start)),
+      message: message.to_string(),
+    };
+
+    let raw: RawConfig = toml::from_str(source).map_err(|e| error(e.span(), e.message()))?;
+
+    let parse_side = |tables: RawModules| {
+      tables
+        .into_iter()
+        .map(|table| {
+          let span = table.span();
+          let mut options = table.into_inner();
+
+          match options.remove("module") {
+            Some(toml::Value::String(name)) => {
+              Ok(ModuleSpec { name, options, pos: Position::from_offset(source, span.start) })
+            }
+            Some(_) => Err(error(Some(span), "`module` must be a string")),
+            None => Err(error(Some(span), "missing `module` key")),
+          }
+        })
+        .collect::<Result<Vec<_>, _>>()
+    };
+    let parse_bar = |geometry, left, center, right| {
+      Ok(BarSpec {
+        geometry,
+        left: parse_side(left)?,
+        center: parse_side(center)?,
+        right: parse_side(right)?,
+      })
+    };
+
+    let geometry = RawGeometry {
+      edge:      raw.edge,
+      size:      raw.size,
+      exclusive: raw.exclusive,
+      layer:     raw.layer,
+      margin:    raw.margin,
+      namespace: raw.namespace,
+    }
+    .apply(&Geometry::default());
+
+    Ok(ConfigSpec {
+      outputs: raw
+        .output
+        .into_iter()
+        .map(|o| {
+          let output_geometry = RawGeometry {
+            edge:      o.edge,
+            size:      o.size,
+            exclusive: o.exclusive,
+            layer:     o.layer,
+            margin:    o.margin,
+            namespace: o.namespace,
+          }
+          .apply(&geometry);
+
+          Ok(OutputSpec {
+            name:        o.name,
+            description: o.description,
+            make:        o.make,
+            model:       o.model,
+            exclude:     o.exclude,
+            bar:         parse_bar(output_geometry, o.left, o.center, o.right)?,
+          })
+        })
+        .collect::<Result<Vec<_>, _>>()?,
+      bar:     parse_bar(geometry, raw.left, raw.center, raw.right)?,
+    })
+  }
+
+  /// Returns the bar to show on `output`, or `None` if it is excluded.
+  pub fn bar_for(&self, output: &OutputInfo) -> Option<&BarSpec> {
+    match self.outputs.iter().find(|o| o.matches(output)) {
+      Some(o) if o.exclude => None,
+      Some(o) => Some(&o.bar),
+      None => Some(&self.bar),
+    }
+  }
+}
+
+impl RawGeometry {
+  fn apply(self, base: &Geometry) -> Geometry {
+    Geometry {
+      edge:      self.edge.unwrap_or(base.edge),
+      size:      self.size.unwrap_or(base.size),
+      exclusive: self.exclusive.unwrap_or(base.exclusive),
+      layer:     self.layer.unwrap_or(base.layer),
+      margin:    self.margin.unwrap_or(base.margin),
+      namespace: self.namespace.unwrap_or_else(|| base.namespace.clone()),
+    }
+  }
+}
+
+impl OutputSpec {
+  fn matches(&self, output: &OutputInfo) -> bool {
+    let matches = |expected: &Option<String>, actual: &str| {
+      expected.as_ref().is_none_or(|expected| expected == actual)
+    };
+
+    matches(&self.name, &output.name)
+      && matches(&self.description, &output.description)
+      && matches(&self.make, &output.make)
+      && matches(&self.model, &output.model)
+  }
+}
+
+impl BarSpec {
+  fn modules(&self) -> impl Iterator<Item = &ModuleSpec> {
+    self.left.iter().chain(self.center.iter()).chain(self.right.iter())
+  }
+}
+
+impl PartialEq for ModuleSpec {
+  // Ignore the position, so that moving a module around in the file doesn't
+  // count as a change.
+  fn eq(&self, other: &Self) -> bool { self.name == other.name && self.options == other.options }
+}
+
+impl Position {
+  fn from_offset(source: &str, offset: usize) -> Self {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+    Position { line, column }
+  }
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.path.display())?;
+    if let Some(pos) = self.pos {
+      write!(f, ":{}:{}", pos.line, pos.column)?;
+    }
+    write!(f, ": {}", self.message)
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Deserializes a CSS color string, like `"white"`, `"#ff0000"`, or
+/// `"oklch(0.7 0.2 310)"`.
+pub fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+  let s = String::deserialize(deserializer)?;
+
+  peniko::color::parse_color(&s)
+    .map(|c| c.to_alpha_color())
+    .map_err(|e| serde::de::Error::custom(format!("invalid color `{s}`: {e}")))
+}
+
+/// Deserializes a duration from a number of seconds.
+pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+  let secs = f64::deserialize(deserializer)?;
+
+  Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+const ERROR_COLOR: Color = Color::new([0.63, 0.22, 0.12, 1.0]);
+
+/// Shows an error message in place of the whole bar.
+struct ErrorModule {
+  message: String,
+  text:    Option<TextLayout>,
+}
+
+impl Bar {
+  pub fn error(error: impl fmt::Display) -> Bar {
+    // Error messages from `toml` can span multiple lines, and we only have room
+    // for one.
+    let message = error.to_string().lines().map(str::trim).collect::<Vec<_>>().join(" ");
+
+    Bar {
+      left_modules:   vec![Box::new(ErrorModule { message, text: None })],
+      center_modules: vec![],
+      right_modules:  vec![],
+    }
+  }
+}
+
+impl Module for ErrorModule {
+  fn layout(&mut self, layout: &mut Layout) {
+    layout.pad(5.0);
+    self.text = Some(layout.layout_text(&self.message, ERROR_COLOR));
+    layout.pad(5.0);
+  }
+
+  fn render(&self, render: &mut Render) {
+    if let Some(text) = &self.text {
+      render.draw(text);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(source: &str) -> Result<ConfigSpec, ConfigError> {
+    ConfigSpec::parse(Path::new("config.toml"), source)
+  }
+
+  fn output(name: &str, make: &str) -> OutputInfo {
+    OutputInfo { name: name.into(), make: make.into(), ..Default::default() }
+  }
+
+  #[test]
+  fn parses_modules() {
+    let spec = parse(
+      r#"
+[[left]]
+module = "clock"
+primary = "white"
+
+[[right]]
+module = "battery"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(spec.bar.left.len(), 1);
+    assert_eq!(spec.bar.left[0].name, "clock");
+    assert_eq!(spec.bar.left[0].options["primary"].as_str(), Some("white"));
+    // The `module` key is taken out of the options.
+    assert!(!spec.bar.left[0].options.contains_key("module"));
+    assert!(spec.bar.center.is_empty());
+    assert_eq!(spec.bar.right[0].name, "battery");
+    assert_eq!(spec.bar.geometry, Geometry::default());
+  }
+
+  #[test]
+  fn reports_where_errors_are() {
+    let err = parse("[[left]]\nprimary = \"white\"\n").unwrap_err();
+    assert_eq!(err.to_string(), "config.toml:1:1: missing `module` key");
+
+    let err = parse("size = 40\n\n[[right]]\nmodule = 3\n").unwrap_err();
+    assert_eq!(err.to_string(), "config.toml:3:1: `module` must be a string");
+
+    let err = parse("height = 40\n").unwrap_err();
+    assert!(err.to_string().starts_with("config.toml:1:1: unknown field `height`"), "{err}");
+  }
+
+  #[test]
+  fn parses_geometry() {
+    let spec = parse(
+      r#"
+edge = "left"
+size = 50
+exclusive = true
+layer = "top"
+margin = { top = 5, left = 6 }
+namespace = "bar"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      spec.bar.geometry,
+      Geometry {
+        edge:      Edge::Left,
+        size:      50,
+        exclusive: Exclusive::All(true),
+        layer:     Layer::Top,
+        margin:    Margin { top: 5, left: 6, ..Default::default() },
+        namespace: "bar".into(),
+      }
+    );
+
+    let exclusive = |source| parse(source).unwrap().bar.geometry.exclusive;
+    assert_eq!(exclusive("exclusive = false"), Exclusive::All(false));
+    assert_eq!(exclusive("exclusive = 20"), Exclusive::Size(20));
+    assert!(parse("exclusive = \"yes\"").is_err());
+  }
+
+  #[test]
+  fn outputs_inherit_geometry() {
+    let spec = parse(
+      r#"
+size = 50
+edge = "bottom"
+
+[[output]]
+name = "eDP-1"
+size = 30
+
+[[output.left]]
+module = "clock"
+"#,
+    )
+    .unwrap();
+
+    let bar = &spec.outputs[0].bar;
+    assert_eq!(bar.geometry.size, 30);
+    assert_eq!(bar.geometry.edge, Edge::Bottom);
+    assert_eq!(bar.left[0].name, "clock");
+    assert!(spec.bar.left.is_empty());
+  }
+
+  #[test]
+  fn picks_bar_for_output() {
+    let spec = parse(
+      r#"
+[[left]]
+module = "top-level"
+
+[[output]]
+name = "eDP-1"
+[[output.left]]
+module = "laptop"
+
+[[output]]
+make = "LG Electronics"
+exclude = true
+
+[[output]]
+name = "eDP-1"
+[[output.left]]
+module = "unused"
+"#,
+    )
+    .unwrap();
+
+    let module = |output: &OutputInfo| spec.bar_for(output).map(|bar| bar.left[0].name.as_str());
+    assert_eq!(module(&output("eDP-1", "")), Some("laptop"));
+    assert_eq!(module(&output("DP-1", "LG Electronics")), None);
+    assert_eq!(module(&output("DP-2", "Dell")), Some("top-level"));
+  }
+
+  #[derive(Deserialize)]
+  #[allow(dead_code)]
+  struct Options {
+    #[serde(deserialize_with = "color")]
+    color:    Color,
+    #[serde(deserialize_with = "duration")]
+    interval: Duration,
+  }
+
+  struct Empty;
+  impl Module for Empty {
+    fn layout(&mut self, _: &mut Layout) {}
+    fn render(&self, _: &mut Render) {}
+  }
+  impl From<Options> for Box<dyn Module> {
+    fn from(_: Options) -> Self { Box::new(Empty) }
+  }
+
+  #[test]
+  fn validates_options() {
+    let mut registry = Registry::new();
+    registry.register::<Options>("test");
+
+    let validate = |source: &str| {
+      let spec = parse(source).unwrap();
+      registry.validate(&spec.bar.left[0])
+    };
+
+    assert_eq!(validate("[[left]]\nmodule = \"test\"\ncolor = \"red\"\ninterval = 0.5"), Ok(()));
+    assert_eq!(validate("[[left]]\nmodule = \"other\""), Err("unknown module `other`".into()));
+
+    let err = validate("[[left]]\nmodule = \"test\"\ncolor = \"nope\"\ninterval = 1").unwrap_err();
+    assert!(err.starts_with("module `test`: invalid color `nope`"), "{err}");
+
+    let err = validate("[[left]]\nmodule = \"test\"\ncolor = \"red\"\ninterval = -1").unwrap_err();
+    assert!(err.starts_with("module `test`: "), "{err}");
+  }
+}
@@ -1,15 +1,29 @@
-use std::sync::Arc;
+use std::{rc::Rc, sync::Arc};
 
-use cb_core::{Color, Drawable, RenderStore, Text, Waker};
-use kurbo::{Point, Rect, Size};
+use cb_core::{Color, Drawable, Edge, EventLoop, RenderStore, Text, Waker};
+use kurbo::{Point, Rect, Size, Vec2};
 
 pub struct Layout<'a> {
   pub(crate) store: &'a mut RenderStore,
   pub(crate) scale: f64,
+  /// The size of the whole bar.
+  pub(crate) size:  Size,
+  pub(crate) axis:  Axis,
 
   pub(crate) bounds: Rect,
 
-  pub waker: &'a Arc<Waker>,
+  pub waker:  &'a Arc<Waker>,
+  /// Event sources registered here are serviced on the main thread.
+  pub events: &'a Rc<EventLoop>,
+}
+
+/// The direction modules are laid out in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+  /// Modules are laid out left to right, for bars on the top or bottom edge.
+  Horizontal,
+  /// Modules are laid out top to bottom, for bars on the left or right edge.
+  Vertical,
 }
 
 pub struct TextLayout {
@@ -24,11 +38,67 @@ impl TextLayout {
   }
 }
 
+impl Axis {
+  /// Returns the length of `size` along this axis.
+  pub fn extent(self, size: Size) -> f64 {
+    match self {
+      Axis::Horizontal => size.width,
+      Axis::Vertical => size.height,
+    }
+  }
+
+  /// Moves `rect` by `distance` along this axis.
+  pub fn translate(self, rect: Rect, distance: f64) -> Rect {
+    match self {
+      Axis::Horizontal => rect + Vec2::new(distance, 0.0),
+      Axis::Vertical => rect + Vec2::new(0.0, distance),
+    }
+  }
+
+  /// Moves `rect` so that it starts at `pos` along this axis.
+  pub(crate) fn place(self, rect: Rect, pos: f64) -> Rect {
+    match self {
+      Axis::Horizontal => self.translate(rect, pos - rect.x0),
+      Axis::Vertical => self.translate(rect, pos - rect.y0),
+    }
+  }
+}
+
+impl From<Edge> for Axis {
+  fn from(edge: Edge) -> Self {
+    match edge {
+      Edge::Top | Edge::Bottom => Axis::Horizontal,
+      Edge::Left | Edge::Right => Axis::Vertical,
+    }
+  }
+}
+
 impl Layout<'_> {
-  pub fn pad(&mut self, gap: f64) { self.bounds.x1 += gap; }
+  /// The direction this module is being laid out in.
+  pub fn axis(&self) -> Axis { self.axis }
+
+  /// Adds `gap` to the end of the module, along the bar's axis.
+  pub fn pad(&mut self, gap: f64) {
+    match self.axis {
+      Axis::Horizontal => self.bounds.x1 += gap,
+      Axis::Vertical => self.bounds.y1 += gap,
+    }
+  }
 
+  /// Lays out text after the end of the module, centered across the bar.
   pub fn layout_text<'a>(&mut self, text: impl Into<Text<'a>>, color: Color) -> TextLayout {
-    self.layout_text_at(Point::new(self.bounds.width(), 8.0), text, color)
+    let layout = self.shape_text(text, color);
+
+    let width = f64::from(layout.full_width()) / self.scale;
+    let height = f64::from(layout.height()) / self.scale;
+    let origin = match self.axis {
+      Axis::Horizontal => {
+        Point::new(self.bounds.width(), ((self.size.height - height) / 2.0).round())
+      }
+      Axis::Vertical => Point::new(((self.size.width - width) / 2.0).round(), self.bounds.height()),
+    };
+
+    self.place_text(origin, layout)
   }
 
   pub fn layout_text_at<'a>(
@@ -37,12 +107,19 @@ impl Layout<'_> {
     text: impl Into<Text<'a>>,
     color: Color,
   ) -> TextLayout {
-    let mut layout =
-      text.into().layout(&mut self.store, cb_core::encode_color(color).into(), self.scale);
+    let layout = self.shape_text(text, color);
+    self.place_text(origin, layout)
+  }
 
-    layout.break_all_lines(None);
-    layout.align(None, parley::Alignment::Start, parley::AlignmentOptions::default());
+  fn shape_text<'a>(
+    &mut self,
+    text: impl Into<Text<'a>>,
+    color: Color,
+  ) -> parley::Layout<peniko::Brush> {
+    self.store.layout_text(text.into(), color, self.scale)
+  }
 
+  fn place_text(&mut self, origin: Point, layout: parley::Layout<peniko::Brush>) -> TextLayout {
     let layout = TextLayout { origin, scale: self.scale, layout };
 
     self.bounds = self.bounds.union(layout.bounds());
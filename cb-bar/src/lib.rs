@@ -1,26 +1,50 @@
 use std::{
   collections::HashMap,
   ops::{Index, IndexMut},
+  rc::Rc,
   sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
   },
 };
 
-use cb_core::{BarId, Render, RenderStore, Waker};
+use cb_core::{
+  BarId, Click, Damage, EventLoop, Fragment, Geometry, OutputInfo, PopupKind, PopupRequest, Render,
+  RenderStore, Scroll, Text, Waker,
+};
 use kurbo::{Point, Rect, Size};
 
 mod animation;
+pub mod config;
 mod layout;
+mod popup;
+#[cfg(feature = "headless")]
+mod preview;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+mod watch;
 
 pub use animation::Animation;
-pub use layout::{Layout, TextLayout};
+#[cfg(feature = "headless")]
+pub use cb_backend_headless::Image;
+pub use config::{Config, Registry};
+pub use layout::{Axis, Layout, TextLayout};
+pub use popup::Popup;
 
 pub trait Module {
   fn updater(&self) -> Updater<'_> { Updater::None }
   fn on_hover(&mut self, hover: bool) { let _ = hover; }
   fn on_mouse(&mut self, cursor: Point) { let _ = cursor; }
-  fn on_click(&mut self, cursor: Point) { let _ = cursor; }
+  /// Called when a mouse button is pressed over this module, and when it is
+  /// released again, even if the mouse has left the module since.
+  fn on_click(&mut self, cursor: Point, click: Click) { let _ = (cursor, click); }
+  fn on_scroll(&mut self, cursor: Point, scroll: Scroll) { let _ = (cursor, scroll); }
+  /// Called after `on_click` for every button press. Returning a popup opens
+  /// it, anchored to this module, and closes any popup that was already open.
+  fn popup(&mut self) -> Option<Popup> { None }
+  /// Returns the text to show in a tooltip, once the mouse has rested on this
+  /// module for a moment. The tooltip closes when the mouse leaves.
+  fn tooltip(&self) -> Option<Text<'static>> { None }
   fn layout(&mut self, layout: &mut Layout);
   fn render(&self, render: &mut Render);
 }
@@ -29,9 +53,27 @@ pub enum Updater<'a> {
   None,
   Animation,
   Every(std::time::Duration),
+  /// Update once this time has passed. Use [`next_tick`] to line this up with
+  /// the wall clock.
+  At(std::time::Instant),
   Atomic(&'a AtomicBool),
 }
 
+/// Returns the next time the wall clock is a multiple of `interval`, like the
+/// start of the next second or minute.
+pub fn next_tick(interval: std::time::Duration) -> std::time::Instant {
+  let now = std::time::Instant::now();
+  let since_epoch = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos();
+
+  let interval_nanos = interval.as_nanos().max(1);
+  let remaining = interval_nanos - since_epoch % interval_nanos;
+  now + std::time::Duration::from_nanos(remaining as u64)
+}
+
+#[derive(Default)]
 pub struct Bar {
   pub left_modules:   Vec<Box<dyn Module>>,
   pub center_modules: Vec<Box<dyn Module>>,
@@ -51,12 +93,32 @@ struct ModuleKey {
   index: usize,
 }
 
+/// How long the mouse has to rest on a module before its tooltip shows.
+const TOOLTIP_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TooltipState {
+  Hidden,
+  /// The mouse started hovering a module at this time.
+  Waiting(std::time::Instant),
+  /// The tooltip of this module is open.
+  Shown(ModuleKey),
+}
+
 struct BarLayout {
-  size:        Size,
-  scale:       f64,
-  last_draw:   std::time::Instant,
-  force_dirty: bool,
-  hover:       Option<ModuleKey>,
+  size:         Size,
+  scale:        f64,
+  axis:         Axis,
+  force_dirty:  bool,
+  /// Set when the cursor moved since the bar was last drawn.
+  cursor_moved: bool,
+  hover:        Option<ModuleKey>,
+  /// The module the last button press went to, which gets the release.
+  pressed:      Option<ModuleKey>,
+  /// A popup opened by a module, the bounds of that module, and the serial of
+  /// the press that opened it.
+  popup:        Option<(Rect, Popup, u32)>,
+  tooltip:      TooltipState,
 
   left_modules:   Vec<ModuleLayout>,
   center_modules: Vec<ModuleLayout>,
@@ -66,101 +128,140 @@ struct BarLayout {
 struct ModuleLayout {
   module: Box<dyn Module>,
   bounds: Rect,
-}
 
-pub struct Config {
-  pub make_bar: fn() -> Bar,
+  /// When this module was last drawn, which is also when it was last laid
+  /// out.
+  last_draw:    std::time::Instant,
+  /// What this module drew last time, which is reused until it's dirty.
+  fragment:     Option<Fragment>,
+  /// The bounds this module had when `fragment` was recorded.
+  drawn_bounds: Rect,
+
+  /// The spec this module was built from, if it came from the config file.
+  spec: Option<config::ModuleSpec>,
 }
 
 struct App {
-  config: Config,
-  bars:   HashMap<BarId, BarLayout>,
+  config:   Config,
+  spec:     Result<config::ConfigSpec, config::ConfigError>,
+  watcher:  Option<watch::Watcher>,
+  /// All the bars and popups. Only bars have an output.
+  bars:     HashMap<BarId, BarLayout>,
+  outputs:  HashMap<BarId, OutputInfo>,
+  /// Popups returned from `take_popup`, which are waiting for their surface.
+  pending:  HashMap<BarId, Popup>,
+  /// Open tooltips, and the bar and module they belong to.
+  tooltips: HashMap<BarId, (BarId, ModuleKey)>,
 
   render: cb_core::RenderStore,
   waker:  Arc<cb_core::Waker>,
+  events: Rc<EventLoop>,
 }
 
 pub fn run(config: Config) { cb_backend_wayland::setup::<App>(config); }
 
+/// Renders `bar` into an image, without needing a display or a GPU. `width`
+/// and `height` are in logical pixels.
+#[cfg(feature = "headless")]
+pub fn screenshot(bar: Bar, axis: Axis, scale: f64, width: u32, height: u32) -> Image {
+  let config = preview::PreviewBar { bar, axis, frame_time: None, font: None, cursor: None };
+  let mut headless = cb_backend_headless::Headless::<preview::Preview>::new(config);
+  headless.render(&OutputInfo::default(), scale, width, height)
+}
+
 impl Bar {
   fn into_layout(self, size: Size, scale: f64) -> BarLayout {
     BarLayout {
       size,
       scale,
-      last_draw: std::time::Instant::now(),
+      axis: Axis::Horizontal,
       force_dirty: true,
+      cursor_moved: false,
       hover: None,
+      pressed: None,
+      popup: None,
+      tooltip: TooltipState::Hidden,
 
-      left_modules: self
-        .left_modules
-        .into_iter()
-        .map(|m| ModuleLayout { module: m, bounds: Rect::ZERO })
-        .collect(),
-      center_modules: self
-        .center_modules
-        .into_iter()
-        .map(|m| ModuleLayout { module: m, bounds: Rect::ZERO })
-        .collect(),
-      right_modules: self
-        .right_modules
-        .into_iter()
-        .map(|m| ModuleLayout { module: m, bounds: Rect::ZERO })
-        .collect(),
+      left_modules: self.left_modules.into_iter().map(|m| ModuleLayout::new(m, None)).collect(),
+      center_modules: self.center_modules.into_iter().map(|m| ModuleLayout::new(m, None)).collect(),
+      right_modules: self.right_modules.into_iter().map(|m| ModuleLayout::new(m, None)).collect(),
     }
   }
 }
 
 impl BarLayout {
-  fn layout(&mut self, store: &mut RenderStore, waker: &Arc<Waker>) {
-    let elapsed = std::time::Instant::now().duration_since(self.last_draw);
-
-    let mut x = 0.0;
-    for module in &mut self.left_modules {
-      if self.force_dirty || module.layout_dirty(elapsed) {
-        module.layout(store, self.scale, waker);
-        module.bounds.x0 += x;
-        module.bounds.x1 += x;
-        x += module.bounds.size().width;
-      } else {
-        let width = module.bounds.size().width;
-        module.bounds.x0 = x;
-        module.bounds.x1 = x + width;
-        x += width;
-      }
-    }
+  /// Rebuilds all the modules in this bar from `spec`. Modules whose spec
+  /// didn't change are kept, along with all their state.
+  fn reload(&mut self, config: &Config, spec: &config::BarSpec) {
+    let mut old = self
+      .left_modules
+      .drain(..)
+      .chain(self.center_modules.drain(..))
+      .chain(self.right_modules.drain(..))
+      .filter(|m| m.spec.is_some())
+      .collect::<Vec<_>>();
+
+    let mut build_side = |specs: &[config::ModuleSpec]| {
+      specs
+        .iter()
+        .map(|spec| match old.iter().position(|m| m.spec.as_ref() == Some(spec)) {
+          Some(i) => old.remove(i),
+          None => ModuleLayout::new(config.build_module(spec), Some(spec.clone())),
+        })
+        .collect::<Vec<_>>()
+    };
 
-    let mut x = 0.0;
-    for module in &mut self.center_modules {
-      if self.force_dirty || module.layout_dirty(elapsed) {
-        module.layout(store, self.scale, waker);
-        x += module.bounds.size().width;
-      } else {
-        let width = module.bounds.size().width;
-        module.bounds.x0 = 0.0;
-        module.bounds.x1 = width;
-        x += width;
+    self.left_modules = build_side(&spec.left);
+    self.center_modules = build_side(&spec.center);
+    self.right_modules = build_side(&spec.right);
+    self.axis = Axis::from(spec.geometry.edge);
+
+    self.hover = None;
+    self.pressed = None;
+    self.tooltip = TooltipState::Hidden;
+    self.force_dirty = true;
+  }
+
+  /// Shows `error` at the start of the bar, while keeping the rest of the
+  /// modules running.
+  fn show_error(&mut self, error: &config::ConfigError) {
+    self.left_modules.retain(|m| m.spec.is_some());
+
+    let module = Bar::error(error).left_modules.remove(0);
+    self.left_modules.insert(0, ModuleLayout::new(module, None));
+
+    self.hover = None;
+    self.pressed = None;
+    self.tooltip = TooltipState::Hidden;
+    self.force_dirty = true;
+  }
+
+  fn layout(&mut self, store: &mut RenderStore, waker: &Arc<Waker>, events: &Rc<EventLoop>) {
+    // Lays out each module in `modules` one after the other, starting at 0,
+    // and returns their total length.
+    let mut layout_side = |modules: &mut [ModuleLayout]| {
+      let mut pos = 0.0;
+      for module in modules {
+        if self.force_dirty || module.layout_dirty() {
+          module.layout(store, self.size, self.scale, self.axis, waker, events);
+        }
+
+        module.bounds = self.axis.place(module.bounds, pos);
+        pos += self.axis.extent(module.bounds.size());
       }
-    }
+      pos
+    };
+
+    layout_side(&mut self.left_modules);
+    let center_len = layout_side(&mut self.center_modules);
+    let right_len = layout_side(&mut self.right_modules);
 
-    let offset = self.size.width - x / 2.0;
+    let len = self.axis.extent(self.size);
     for module in &mut self.center_modules {
-      module.bounds.x0 += offset;
-      module.bounds.x1 += offset;
+      module.bounds = self.axis.translate(module.bounds, (len - center_len) / 2.0);
     }
-
-    let mut x = self.size.width;
-    for module in self.right_modules.iter_mut().rev() {
-      if self.force_dirty || module.layout_dirty(elapsed) {
-        module.layout(store, self.scale, waker);
-        x -= module.bounds.size().width;
-        module.bounds.x0 += x;
-        module.bounds.x1 += x;
-      } else {
-        let width = module.bounds.size().width;
-        module.bounds.x1 = x;
-        x -= width;
-        module.bounds.x0 = x;
-      }
+    for module in &mut self.right_modules {
+      module.bounds = self.axis.translate(module.bounds, len - right_len);
     }
   }
 
@@ -169,8 +270,7 @@ impl BarLayout {
       return true;
     }
 
-    let elapsed = self.last_draw.elapsed();
-    self.modules().any(|m| m.layout_dirty(elapsed))
+    self.modules().any(|m| m.layout_dirty())
   }
 
   fn render_dirty(&self) -> bool {
@@ -178,21 +278,70 @@ impl BarLayout {
       return true;
     }
 
-    let elapsed = self.last_draw.elapsed();
-    self.modules().any(|m| m.render_dirty(elapsed))
+    self.modules().any(|m| m.render_dirty() || (self.cursor_moved && m.follows_cursor()))
   }
 
-  fn draw(&mut self, render: &mut Render) {
-    self.last_draw = render.frame_time();
-    self.force_dirty = false;
+  /// Returns when this bar next needs to be drawn, not counting modules that
+  /// use the waker.
+  fn deadline(&self) -> Option<std::time::Instant> {
+    let now = std::time::Instant::now();
+    let tooltip = match self.tooltip {
+      // Once the delay has passed, the tooltip is picked up by `take_popup`.
+      TooltipState::Waiting(since) => Some(since + TOOLTIP_DELAY).filter(|&t| t > now),
+      _ => None,
+    };
+
+    self.modules().filter_map(|m| m.deadline()).chain(tooltip).min()
+  }
 
-    for module in self.modules() {
-      render.set_offset(module.bounds.origin().to_vec2());
-      module.module.render(render);
+  /// Draws the bar, and returns the regions that changed. Modules that aren't
+  /// dirty, and haven't moved, reuse what they drew last time.
+  /// Draws the modules that changed, and returns where the bar changed, in
+  /// buffer pixels.
+  fn draw(&mut self, render: &mut Render) -> Vec<Damage> {
+    let full = std::mem::take(&mut self.force_dirty);
+    let cursor_moved = std::mem::take(&mut self.cursor_moved);
+
+    let mut damage = vec![];
+    for module in self
+      .left_modules
+      .iter_mut()
+      .chain(self.center_modules.iter_mut())
+      .chain(self.right_modules.iter_mut())
+    {
+      let moved = module.bounds != module.drawn_bounds;
+      let cursor = cursor_moved && module.follows_cursor();
+      if full || moved || cursor || module.fragment.is_none() || module.render_dirty() {
+        render.set_offset(module.bounds.origin().to_vec2());
+        module.fragment = Some(render.record(|render| module.module.render(render)));
+        module.last_draw = render.frame_time();
+
+        damage.push(module.bounds.union(module.drawn_bounds));
+        module.drawn_bounds = module.bounds;
+      }
+
+      render.replay(module.fragment.as_ref().unwrap());
     }
+
+    let damage = if full { vec![self.size.to_rect()] } else { damage };
+    damage
+      .into_iter()
+      .map(|rect| {
+        // Strokes and tilted buttons can be drawn a bit outside of a module.
+        let rect = rect.inflate(4.0, 4.0).scale_from_origin(self.scale).expand();
+        Damage {
+          x:      rect.x0 as i32,
+          y:      rect.y0 as i32,
+          width:  rect.width() as i32,
+          height: rect.height() as i32,
+        }
+      })
+      .collect()
   }
 
   fn move_mouse(&mut self, pos: Option<(f64, f64)>) {
+    self.cursor_moved = true;
+
     let new_hover = if let Some(pos) = pos {
       let pos = Point::new(pos.0, pos.1);
 
@@ -210,6 +359,10 @@ impl BarLayout {
       }
 
       self.hover = new_hover;
+      self.tooltip = match new_hover {
+        Some(_) => TooltipState::Waiting(std::time::Instant::now()),
+        None => TooltipState::Hidden,
+      };
       self.force_dirty = true;
     }
 
@@ -221,14 +374,52 @@ impl BarLayout {
     }
   }
 
-  fn click_mouse(&mut self, pos: (f64, f64)) {
+  fn click_mouse(&mut self, pos: (f64, f64), click: Click) {
+    let pos = Point::new(pos.0, pos.1);
+    let target = if click.pressed {
+      self.tooltip = TooltipState::Hidden;
+
+      let target = self.module_keys().find(|&k| self[k].bounds.contains(pos));
+      self.pressed = target;
+      target
+    } else {
+      self.pressed.take()
+    };
+    let Some(target) = target else { return };
+
+    let m = &mut self[target];
+    m.module.on_click(pos - m.bounds.origin().to_vec2(), click);
+
+    if click.pressed
+      && let Some(popup) = m.module.popup()
+    {
+      self.popup = Some((m.bounds, popup, click.serial));
+    }
+  }
+
+  /// Returns the tooltip of the hovered module, if the mouse has rested on it
+  /// for long enough.
+  fn take_tooltip(&mut self) -> Option<(ModuleKey, Text<'static>)> {
+    let TooltipState::Waiting(since) = self.tooltip else { return None };
+    if since.elapsed() < TOOLTIP_DELAY {
+      return None;
+    }
+
+    let hover = self.hover?;
+    let text = self[hover].module.tooltip();
+    // Modules without a tooltip don't need to be checked again.
+    self.tooltip = TooltipState::Shown(hover);
+    Some((hover, text?))
+  }
+
+  fn scroll_mouse(&mut self, pos: (f64, f64), scroll: Scroll) {
     let pos = Point::new(pos.0, pos.1);
     let Some(hover) = self.module_keys().find(|&k| self[k].bounds.contains(pos)) else {
       return;
     };
 
     let m = &mut self[hover];
-    m.module.on_click(pos - m.bounds.origin().to_vec2());
+    m.module.on_scroll(pos - m.bounds.origin().to_vec2(), scroll);
   }
 
   fn module_keys(&self) -> impl Iterator<Item = ModuleKey> {
@@ -266,104 +457,295 @@ impl IndexMut<ModuleKey> for BarLayout {
 }
 
 impl ModuleLayout {
-  fn layout_dirty(&self, elapsed: std::time::Duration) -> bool {
+  fn new(module: Box<dyn Module>, spec: Option<config::ModuleSpec>) -> Self {
+    ModuleLayout {
+      module,
+      bounds: Rect::ZERO,
+      last_draw: std::time::Instant::now(),
+      fragment: None,
+      drawn_bounds: Rect::ZERO,
+      spec,
+    }
+  }
+
+  fn layout_dirty(&self) -> bool {
     match self.module.updater() {
       Updater::None => false,
       Updater::Animation => false,
-      Updater::Every(interval) => elapsed > interval,
+      Updater::Every(interval) => self.last_draw.elapsed() >= interval,
+      Updater::At(time) => std::time::Instant::now() >= time,
       Updater::Atomic(a) => a.load(Ordering::SeqCst),
     }
   }
-  fn render_dirty(&self, elapsed: std::time::Duration) -> bool {
+  fn render_dirty(&self) -> bool {
     match self.module.updater() {
       Updater::None => false,
       Updater::Animation => true,
-      Updater::Every(interval) => elapsed > interval,
+      Updater::Every(interval) => self.last_draw.elapsed() >= interval,
+      Updater::At(time) => std::time::Instant::now() >= time,
       Updater::Atomic(a) => a.load(Ordering::SeqCst),
     }
   }
+  /// Returns true if what this module drew last time depends on the cursor.
+  fn follows_cursor(&self) -> bool { self.fragment.as_ref().is_some_and(|f| f.follows_cursor()) }
+  fn deadline(&self) -> Option<std::time::Instant> {
+    match self.module.updater() {
+      Updater::None => None,
+      Updater::Animation => Some(self.last_draw),
+      Updater::Every(interval) => Some(self.last_draw + interval),
+      Updater::At(time) => Some(time),
+      // Atomics are paired with the waker.
+      Updater::Atomic(_) => None,
+    }
+  }
 
-  fn layout(&mut self, store: &mut RenderStore, scale: f64, waker: &Arc<Waker>) {
-    let mut ctx = Layout { store, scale, bounds: Rect::ZERO, waker };
+  fn layout(
+    &mut self,
+    store: &mut RenderStore,
+    size: Size,
+    scale: f64,
+    axis: Axis,
+    waker: &Arc<Waker>,
+    events: &Rc<EventLoop>,
+  ) {
+    let mut ctx = Layout { store, scale, size, axis, bounds: Rect::ZERO, waker, events };
     self.module.layout(&mut ctx);
     self.bounds = ctx.bounds;
+    // Whatever was drawn before is out of date now.
+    self.fragment = None;
   }
 }
 
 impl cb_core::App for App {
   type Config = Config;
 
-  fn new(config: Config, device: &cb_core::wgpu::Device) -> Self {
+  fn new(config: Config, device: cb_core::Device) -> Self {
+    let spec = config.load();
+    if let Err(e) = &spec {
+      eprintln!("{e}");
+    }
+
+    let waker = Arc::new(Waker::new());
+    let events = Rc::new(EventLoop::new());
+    let watcher = match watch::Watcher::new(&config.path, &events) {
+      Ok(w) => Some(w),
+      Err(e) => {
+        eprintln!("cannot watch {}: {e}", config.path.display());
+        None
+      }
+    };
+
     App {
       config,
+      spec,
+      watcher,
       bars: HashMap::new(),
+      outputs: HashMap::new(),
+      pending: HashMap::new(),
+      tooltips: HashMap::new(),
       render: cb_core::RenderStore::new(device),
-      waker: Arc::new(Waker::new()),
+      waker,
+      events,
+    }
+  }
+
+  fn update(&mut self) -> bool {
+    if !self.watcher.as_ref().is_some_and(|w| w.changed()) {
+      return false;
+    }
+
+    match self.config.load() {
+      Ok(spec) => {
+        // Bars on outputs that are now excluded are left alone, as the backend
+        // will destroy them once it sees they have no geometry.
+        for (id, bar) in &mut self.bars {
+          if let Some(output) = self.outputs.get(id)
+            && let Some(spec) = spec.bar_for(output)
+          {
+            bar.reload(&self.config, spec);
+          }
+        }
+        self.spec = Ok(spec);
+
+        true
+      }
+      Err(e) => {
+        eprintln!("{e}");
+        for (id, bar) in &mut self.bars {
+          if !id.is_popup() {
+            bar.show_error(&e);
+          }
+        }
+        // Keep the last working config for new bars.
+        if self.spec.is_err() {
+          self.spec = Err(e);
+        }
+
+        false
+      }
     }
   }
 
   fn waker(&self) -> Option<Arc<Waker>> { Some(self.waker.clone()) }
+  fn events(&self) -> Option<Rc<EventLoop>> { Some(self.events.clone()) }
+
+  fn geometry(&self, output: &OutputInfo) -> Option<Geometry> {
+    match &self.spec {
+      Ok(spec) => spec.bar_for(output).map(|bar| bar.geometry.clone()),
+      // Show the error on every output if the config is broken.
+      Err(_) => Some(Geometry::default()),
+    }
+  }
 
   fn create_bar(
     &mut self,
     id: BarId,
-    device: &cb_core::wgpu::Device,
+    output: &OutputInfo,
+    device: cb_core::Device,
+    format: cb_core::wgpu::TextureFormat,
+    scale: f64,
+    width: u32,
+    height: u32,
+  ) {
+    let size = Size::new(f64::from(width), f64::from(height));
+    let mut layout = match &self.spec {
+      Ok(spec) => {
+        let mut layout = Bar::default().into_layout(size, scale);
+        if let Some(spec) = spec.bar_for(output) {
+          layout.reload(&self.config, spec);
+        }
+        layout
+      }
+      Err(e) => Bar::error(e).into_layout(size, scale),
+    };
+    self.outputs.insert(id, output.clone());
+    layout.layout(&mut self.render, &self.waker, &self.events);
+    self.bars.insert(id, layout);
+
+    self.render.create_bar(id, device, format, scale, width, height);
+  }
+
+  fn take_popup(&mut self, id: BarId, popup_id: BarId) -> Option<PopupRequest> {
+    let bar = self.bars.get_mut(&id)?;
+    let (anchor, popup, kind, serial) = if let Some((anchor, popup, serial)) = bar.popup.take() {
+      (anchor, popup, PopupKind::Menu, Some(serial))
+    } else {
+      let (key, text) = bar.take_tooltip()?;
+      let anchor = bar[key].bounds;
+      let scale = bar.scale;
+
+      // Measure the text first, as the popup needs a size up front.
+      let mut module = ModuleLayout::new(Box::new(popup::TooltipModule::new(text)), None);
+      module.layout(
+        &mut self.render,
+        Size::ZERO,
+        scale,
+        Axis::Horizontal,
+        &self.waker,
+        &self.events,
+      );
+      let size = module.bounds.size() + Size::new(popup::TOOLTIP_PAD, popup::TOOLTIP_PAD);
+
+      self.tooltips.insert(popup_id, (id, key));
+      (anchor, Popup { size, content: module.module }, PopupKind::Tooltip, None)
+    };
+
+    let request = PopupRequest {
+      anchor_x: anchor.x0.round() as i32,
+      anchor_y: anchor.y0.round() as i32,
+      anchor_width: (anchor.width().round() as i32).max(1),
+      anchor_height: (anchor.height().round() as i32).max(1),
+      width: popup.size.width.ceil() as u32,
+      height: popup.size.height.ceil() as u32,
+      kind,
+      serial,
+    };
+    self.pending.insert(popup_id, popup);
+    Some(request)
+  }
+
+  fn popup_closed(&self, id: BarId) -> bool {
+    // Menus are closed by the compositor.
+    let Some(&(parent, key)) = self.tooltips.get(&id) else { return false };
+
+    self.bars.get(&parent).is_none_or(|bar| bar.tooltip != TooltipState::Shown(key))
+  }
+
+  fn create_popup(
+    &mut self,
+    id: BarId,
+    device: cb_core::Device,
     format: cb_core::wgpu::TextureFormat,
     scale: f64,
     width: u32,
     height: u32,
   ) {
-    let mut layout = (self.config.make_bar)()
-      .into_layout(Size::new(f64::from(width), f64::from(height)), f64::from(scale));
-    layout.layout(&mut self.render, &self.waker);
+    let Some(popup) = self.pending.remove(&id) else { return };
+
+    let size = Size::new(f64::from(width), f64::from(height));
+    let bar =
+      Bar { left_modules: vec![Box::new(popup::PopupModule::from(popup))], ..Default::default() };
+    let mut layout = bar.into_layout(size, scale);
+    layout.layout(&mut self.render, &self.waker, &self.events);
     self.bars.insert(id, layout);
 
     self.render.create_bar(id, device, format, scale, width, height);
   }
 
+  fn destroy_bar(&mut self, id: BarId) {
+    self.bars.remove(&id);
+    self.outputs.remove(&id);
+    self.pending.remove(&id);
+    self.tooltips.remove(&id);
+    self.render.destroy_bar(id);
+  }
+
   fn dirty(&self, id: BarId) -> bool { self.bars.get(&id).unwrap().render_dirty() }
 
+  fn deadline(&self, id: BarId) -> Option<std::time::Instant> {
+    self.bars.get(&id).and_then(|bar| bar.deadline())
+  }
+
   fn move_mouse(&mut self, id: BarId, pos: Option<(f64, f64)>) {
     self.bars.get_mut(&id).unwrap().move_mouse(pos);
 
     self.render.move_mouse(id, pos);
   }
 
-  fn click_mouse(&mut self, id: BarId, pos: (f64, f64)) {
-    self.bars.get_mut(&id).unwrap().click_mouse(pos);
+  fn click_mouse(&mut self, id: BarId, pos: (f64, f64), click: Click) {
+    self.bars.get_mut(&id).unwrap().click_mouse(pos, click);
   }
 
-  fn draw(
-    &mut self,
-    id: BarId,
-    device: &cb_core::wgpu::Device,
-    queue: &cb_core::wgpu::Queue,
-    output: &cb_core::wgpu::Texture,
-  ) {
+  fn scroll_mouse(&mut self, id: BarId, pos: (f64, f64), scroll: Scroll) {
+    self.bars.get_mut(&id).unwrap().scroll_mouse(pos, scroll);
+  }
+
+  fn draw(&mut self, id: BarId, device: cb_core::Device, output: cb_core::Target) -> Vec<Damage> {
     if self.bars.get(&id).unwrap().layout_dirty() {
-      self.bars.get_mut(&id).unwrap().layout(&mut self.render, &self.waker);
+      self.bars.get_mut(&id).unwrap().layout(&mut self.render, &self.waker, &self.events);
     }
 
-    if let Some(mut render) = self.render.for_bar(id) {
-      self.bars.get_mut(&id).unwrap().draw(&mut render);
-
-      render.render(device, queue, output);
-    }
+    let Some(mut render) = self.render.for_bar(id) else { return vec![] };
+    let bar = self.bars.get_mut(&id).unwrap();
+    let damage = bar.draw(&mut render);
+    render.render(device, output, &damage);
+    damage
   }
 
-  fn set_size(
-    &mut self,
-    id: BarId,
-    device: &cb_core::wgpu::Device,
-    factor: f64,
-    width: u32,
-    height: u32,
-  ) {
+  fn set_size(&mut self, id: BarId, device: cb_core::Device, factor: f64, width: u32, height: u32) {
     self.render.set_size(id, device, factor, width, height);
     let bar = self.bars.get_mut(&id).unwrap();
     bar.size = Size::new(f64::from(width), f64::from(height));
     bar.scale = factor;
     bar.force_dirty = true;
-    bar.layout(&mut self.render, &self.waker);
+    bar.layout(&mut self.render, &self.waker, &self.events);
+  }
+
+  fn set_device(&mut self, device: cb_core::Device) -> Result<(), String> {
+    self.render.set_device(device)?;
+    for bar in self.bars.values_mut() {
+      bar.force_dirty = true;
+    }
+    Ok(())
   }
 }
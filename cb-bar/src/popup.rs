@@ -0,0 +1,75 @@
+//! Popups that modules can open, like a calendar under a clock.
+
+use cb_core::{Click, Color, Render, Scroll, Text};
+use kurbo::{Point, Rect, RoundedRect, Size};
+
+use crate::{Layout, Module, TextLayout, Updater};
+
+/// A popup for a module to open, anchored to the module. The popup closes
+/// when clicking anywhere outside of it.
+pub struct Popup {
+  /// The size of the popup, in logical pixels.
+  pub size:    Size,
+  /// The contents of the popup. This is laid out and rendered just like a
+  /// module in the bar, but is given the whole popup.
+  pub content: Box<dyn Module>,
+}
+
+const BACKGROUND: Color = Color::new([0.2, 0.0, -0.01, 0.95]);
+
+/// The space around the text of a tooltip.
+pub(crate) const TOOLTIP_PAD: f64 = 6.0;
+
+/// Wraps the contents of a popup, to fill in the background.
+pub(crate) struct PopupModule {
+  size:    Size,
+  content: Box<dyn Module>,
+}
+
+impl From<Popup> for PopupModule {
+  fn from(popup: Popup) -> Self { PopupModule { size: popup.size, content: popup.content } }
+}
+
+impl Module for PopupModule {
+  fn updater(&self) -> Updater<'_> { self.content.updater() }
+  fn on_hover(&mut self, hover: bool) { self.content.on_hover(hover); }
+  fn on_mouse(&mut self, cursor: Point) { self.content.on_mouse(cursor); }
+  fn on_click(&mut self, cursor: Point, click: Click) { self.content.on_click(cursor, click); }
+  fn on_scroll(&mut self, cursor: Point, scroll: Scroll) { self.content.on_scroll(cursor, scroll); }
+
+  fn layout(&mut self, layout: &mut Layout) {
+    self.content.layout(layout);
+
+    // The content always gets the whole popup, so that the background and
+    // mouse events cover all of it.
+    layout.bounds = Rect::from_origin_size(Point::ZERO, layout.size);
+  }
+
+  fn render(&self, render: &mut Render) {
+    let rect = Rect::from_origin_size(Point::ZERO, self.size);
+    render.fill(&RoundedRect::from_rect(rect, 8.0), BACKGROUND);
+
+    self.content.render(render);
+  }
+}
+
+/// The contents of a tooltip.
+pub(crate) struct TooltipModule {
+  text:   Text<'static>,
+  layout: TextLayout,
+}
+
+impl TooltipModule {
+  pub(crate) fn new(text: Text<'static>) -> Self {
+    TooltipModule { text, layout: TextLayout::empty() }
+  }
+}
+
+impl Module for TooltipModule {
+  fn layout(&mut self, layout: &mut Layout) {
+    let origin = Point::new(TOOLTIP_PAD, TOOLTIP_PAD);
+    self.layout = layout.layout_text_at(origin, self.text.clone(), Color::WHITE);
+  }
+
+  fn render(&self, render: &mut Render) { render.draw(&self.layout); }
+}
@@ -0,0 +1,130 @@
+//! Renders a single bar without a display, for screenshots and snapshots.
+
+use std::{rc::Rc, sync::Arc, time::Instant};
+
+use cb_core::{
+  BarId, Click, Damage, EventLoop, Geometry, OutputInfo, PopupRequest, RenderStore, Scroll, Waker,
+};
+use kurbo::Size;
+
+use crate::{Axis, Bar, BarLayout};
+
+/// The bar a [`Preview`] shows.
+pub(crate) struct PreviewBar {
+  pub bar:        Bar,
+  pub axis:       Axis,
+  /// If set, the bar is drawn as if it was this time.
+  pub frame_time: Option<Instant>,
+  /// If set, all text is drawn with this font, instead of the system fonts.
+  pub font:       Option<Vec<u8>>,
+  /// Where the cursor is over the bar, in logical pixels.
+  pub cursor:     Option<(f64, f64)>,
+}
+
+/// An app that shows one fixed bar.
+pub(crate) struct Preview {
+  bar:    Option<(Bar, Axis)>,
+  cursor: Option<(f64, f64)>,
+  layout: Option<BarLayout>,
+
+  render: RenderStore,
+  waker:  Arc<Waker>,
+  events: Rc<EventLoop>,
+}
+
+impl cb_core::App for Preview {
+  type Config = PreviewBar;
+
+  fn new(config: PreviewBar, device: cb_core::Device) -> Self {
+    let mut render = RenderStore::new(device);
+    render.set_frame_time(config.frame_time);
+    if let Some(font) = config.font {
+      render.set_font(font);
+    }
+
+    Preview {
+      bar: Some((config.bar, config.axis)),
+      cursor: config.cursor,
+      layout: None,
+      render,
+      waker: Arc::new(Waker::new()),
+      events: Rc::new(EventLoop::new()),
+    }
+  }
+
+  fn waker(&self) -> Option<Arc<Waker>> { Some(self.waker.clone()) }
+  fn events(&self) -> Option<Rc<EventLoop>> { Some(self.events.clone()) }
+  fn update(&mut self) -> bool { false }
+  fn geometry(&self, _: &OutputInfo) -> Option<Geometry> { Some(Geometry::default()) }
+
+  fn create_bar(
+    &mut self,
+    id: BarId,
+    _: &OutputInfo,
+    device: cb_core::Device,
+    format: cb_core::wgpu::TextureFormat,
+    scale: f64,
+    width: u32,
+    height: u32,
+  ) {
+    // The bar is only created once.
+    let Some((bar, axis)) = self.bar.take() else { return };
+    let mut layout = bar.into_layout(Size::new(f64::from(width), f64::from(height)), scale);
+    layout.axis = axis;
+    layout.layout(&mut self.render, &self.waker, &self.events);
+
+    self.render.create_bar(id, device, format, scale, width, height);
+
+    if self.cursor.is_some() {
+      layout.move_mouse(self.cursor);
+      self.render.move_mouse(id, self.cursor);
+    }
+    self.layout = Some(layout);
+  }
+
+  fn take_popup(&mut self, _: BarId, _: BarId) -> Option<PopupRequest> { None }
+  fn popup_closed(&self, _: BarId) -> bool { false }
+  fn create_popup(
+    &mut self,
+    _: BarId,
+    _: cb_core::Device,
+    _: cb_core::wgpu::TextureFormat,
+    _: f64,
+    _: u32,
+    _: u32,
+  ) {
+  }
+
+  fn destroy_bar(&mut self, id: BarId) {
+    self.layout = None;
+    self.render.destroy_bar(id);
+  }
+
+  fn set_size(&mut self, id: BarId, device: cb_core::Device, factor: f64, width: u32, height: u32) {
+    self.render.set_size(id, device, factor, width, height);
+    let Some(layout) = &mut self.layout else { return };
+    layout.size = Size::new(f64::from(width), f64::from(height));
+    layout.scale = factor;
+    layout.force_dirty = true;
+    layout.layout(&mut self.render, &self.waker, &self.events);
+  }
+
+  fn set_device(&mut self, device: cb_core::Device) -> Result<(), String> {
+    self.render.set_device(device)
+  }
+
+  fn dirty(&self, _: BarId) -> bool { true }
+  fn deadline(&self, _: BarId) -> Option<std::time::Instant> { None }
+  fn move_mouse(&mut self, _: BarId, _: Option<(f64, f64)>) {}
+  fn click_mouse(&mut self, _: BarId, _: (f64, f64), _: Click) {}
+  fn scroll_mouse(&mut self, _: BarId, _: (f64, f64), _: Scroll) {}
+
+  fn draw(&mut self, id: BarId, device: cb_core::Device, output: cb_core::Target) -> Vec<Damage> {
+    let Some(layout) = &mut self.layout else { return vec![] };
+    if let Some(mut render) = self.render.for_bar(id) {
+      let damage = layout.draw(&mut render);
+      render.render(device, output, &damage);
+    }
+    vec![]
+  }
+}
@@ -0,0 +1,285 @@
+//! Compares how bars and modules are drawn against reference images, to catch
+//! changes in drawing.
+//!
+//! References are stored as `<name>.png` in the snapshot directory. When a
+//! snapshot doesn't match, what was drawn is written to `<name>.new.png`, and
+//! the pixels that differ are highlighted in `<name>.diff.png`. Set
+//! `CB_UPDATE_SNAPSHOTS=1` to overwrite the references instead.
+//!
+//! Snapshots are drawn on the CPU, which draws the same pixels on every
+//! machine. Set a [`font`](Snapshot::font) too, as the system fonts differ
+//! between machines, or start from [`Snapshot::fixture`], which does.
+
+use std::{
+  fmt, io,
+  path::{Path, PathBuf},
+  sync::LazyLock,
+  time::{Duration, Instant},
+};
+
+use cb_core::Color;
+use peniko::color::{AlphaColor, Oklab, Srgb};
+
+use crate::{Axis, Bar, Image, Module, preview};
+
+/// The font that [`Snapshot::fixture`] draws text with.
+const FONT: &[u8] = include_bytes!("../tests/fonts/DejaVuSansMono.ttf");
+
+/// Returns the time that snapshots are drawn at, unless they set another. It's
+/// a fixed offset from when the first snapshot of the process was created,
+/// so every snapshot draws the same frame, and modules created since then see
+/// it as the present.
+pub fn frame_time() -> Instant {
+  static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+  *EPOCH + Duration::from_secs(60)
+}
+
+/// The color fixtures draw values in.
+pub fn primary() -> Color { cb_core::oklch(0.9, 0.05, 260.0) }
+
+/// The color fixtures draw units and labels in.
+pub fn secondary() -> Color { cb_core::oklch(0.6, 0.08, 260.0) }
+
+/// The environment variable that makes snapshots overwrite their references.
+const UPDATE_VAR: &str = "CB_UPDATE_SNAPSHOTS";
+
+pub struct Snapshot {
+  dir:        PathBuf,
+  axis:       Axis,
+  scale:      f64,
+  tolerance:  f32,
+  frame_time: Instant,
+  font:       Option<Vec<u8>>,
+  cursor:     Option<(f64, f64)>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+  /// Reading or writing one of the images failed.
+  Io(PathBuf, io::Error),
+  /// There is no reference image yet.
+  Missing(PathBuf),
+  /// The reference image has a different size.
+  Size { path: PathBuf, expected: (u32, u32), actual: (u32, u32) },
+  /// Some pixels differ by more than the tolerance.
+  Mismatch { path: PathBuf, pixels: usize, max: f32 },
+}
+
+impl Snapshot {
+  /// Creates a snapshot harness that stores its references in `dir`. All
+  /// frames are drawn at [`frame_time`].
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Snapshot {
+      dir:        dir.into(),
+      axis:       Axis::Horizontal,
+      scale:      1.0,
+      tolerance:  0.02,
+      frame_time: frame_time(),
+      font:       None,
+      cursor:     None,
+    }
+  }
+
+  /// Creates a snapshot harness like [`Snapshot::new`], that draws text with
+  /// DejaVu Sans Mono. The tests of modules start from this.
+  pub fn fixture(dir: impl Into<PathBuf>) -> Self { Snapshot::new(dir).font(FONT) }
+
+  pub fn axis(mut self, axis: Axis) -> Self {
+    self.axis = axis;
+    self
+  }
+
+  pub fn scale(mut self, scale: f64) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  /// Sets how far apart two pixels may be, as a distance in OkLab, before
+  /// they count as different. The default is about the smallest difference
+  /// that can be seen.
+  pub fn tolerance(mut self, tolerance: f32) -> Self {
+    self.tolerance = tolerance;
+    self
+  }
+
+  /// Sets the time that all frames are drawn at, for modules that animate.
+  pub fn frame_time(mut self, time: Instant) -> Self {
+    self.frame_time = time;
+    self
+  }
+
+  /// Draws all text with the font in `data`, instead of the system fonts.
+  pub fn font(mut self, data: impl Into<Vec<u8>>) -> Self {
+    self.font = Some(data.into());
+    self
+  }
+
+  /// Draws bars with the cursor at `pos`, in logical pixels, for modules that
+  /// react to it.
+  pub fn cursor(mut self, pos: Option<(f64, f64)>) -> Self {
+    self.cursor = pos;
+    self
+  }
+
+  /// Draws `module` on its own, and compares it against the reference
+  /// `name`. `width` and `height` are in logical pixels.
+  pub fn check_module(
+    &self,
+    name: &str,
+    module: Box<dyn Module>,
+    width: u32,
+    height: u32,
+  ) -> Result<(), SnapshotError> {
+    let bar = Bar { left_modules: vec![module], ..Default::default() };
+    self.check_bar(name, bar, width, height)
+  }
+
+  /// Draws `bar`, and compares it against the reference `name`. `width` and
+  /// `height` are in logical pixels.
+  pub fn check_bar(
+    &self,
+    name: &str,
+    bar: Bar,
+    width: u32,
+    height: u32,
+  ) -> Result<(), SnapshotError> {
+    let image = self.render(bar, width, height);
+    self.compare(name, &image)
+  }
+
+  /// Draws `bar` on the CPU, with the settings of this snapshot.
+  pub fn render(&self, bar: Bar, width: u32, height: u32) -> Image {
+    let mut headless = cb_backend_headless::Headless::<preview::Preview>::new_cpu(self.config(bar));
+    headless.render(&Default::default(), self.scale, width, height)
+  }
+
+  /// Draws `bar` on a GPU, or the software adapter, with the settings of this
+  /// snapshot. Returns `None` if there's neither.
+  ///
+  /// The GPU antialiases differently than the CPU, so compare these against
+  /// references with a higher [`tolerance`](Snapshot::tolerance).
+  pub fn render_gpu(&self, bar: Bar, width: u32, height: u32) -> Option<Image> {
+    let mut headless =
+      cb_backend_headless::Headless::<preview::Preview>::new_gpu(self.config(bar))?;
+    Some(headless.render(&Default::default(), self.scale, width, height))
+  }
+
+  fn config(&self, bar: Bar) -> preview::PreviewBar {
+    preview::PreviewBar {
+      bar,
+      axis: self.axis,
+      frame_time: Some(self.frame_time),
+      font: self.font.clone(),
+      cursor: self.cursor,
+    }
+  }
+
+  /// Compares `image` against the reference `name`.
+  pub fn compare(&self, name: &str, image: &Image) -> Result<(), SnapshotError> {
+    let path = self.dir.join(format!("{name}.png"));
+    let new_path = self.dir.join(format!("{name}.new.png"));
+    let diff_path = self.dir.join(format!("{name}.diff.png"));
+
+    // Clear out the results of an earlier failure.
+    let _ = std::fs::remove_file(&new_path);
+    let _ = std::fs::remove_file(&diff_path);
+
+    if std::env::var_os(UPDATE_VAR).is_some_and(|v| !v.is_empty() && v != "0") {
+      return save(image, &path);
+    }
+
+    let expected = match Image::load(&path) {
+      Ok(image) => image,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        save(image, &new_path)?;
+        return Err(SnapshotError::Missing(path));
+      }
+      Err(e) => return Err(SnapshotError::Io(path, e)),
+    };
+
+    if (expected.width, expected.height) != (image.width, image.height) {
+      save(image, &new_path)?;
+      return Err(SnapshotError::Size {
+        path,
+        expected: (expected.width, expected.height),
+        actual: (image.width, image.height),
+      });
+    }
+
+    let mut diff =
+      Image { width: image.width, height: image.height, pixels: expected.pixels.clone() };
+    let mut pixels = 0;
+    let mut max = 0.0_f32;
+    for ((a, b), out) in expected
+      .pixels
+      .chunks_exact(4)
+      .zip(image.pixels.chunks_exact(4))
+      .zip(diff.pixels.chunks_exact_mut(4))
+    {
+      let distance = distance(a, b);
+      max = max.max(distance);
+
+      if distance > self.tolerance {
+        pixels += 1;
+        out.copy_from_slice(&[255, 0, 0, 255]);
+      } else {
+        // Fade out the pixels that match, so the differences stand out.
+        let gray = ((u16::from(a[0]) + u16::from(a[1]) + u16::from(a[2])) / 9) as u8;
+        out.copy_from_slice(&[gray, gray, gray, 255]);
+      }
+    }
+
+    if pixels > 0 {
+      save(image, &new_path)?;
+      save(&diff, &diff_path)?;
+      return Err(SnapshotError::Mismatch { path, pixels, max });
+    }
+
+    Ok(())
+  }
+}
+
+fn save(image: &Image, path: &Path) -> Result<(), SnapshotError> {
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir).map_err(|e| SnapshotError::Io(dir.to_path_buf(), e))?;
+  }
+  image.save(path).map_err(|e| SnapshotError::Io(path.to_path_buf(), e))
+}
+
+/// Returns how different two RGBA pixels look. This is the distance between
+/// them in OkLab, plus the difference in alpha.
+fn distance(a: &[u8], b: &[u8]) -> f32 {
+  let [a, b] = [a, b].map(|p| AlphaColor::<Srgb>::from_rgba8(p[0], p[1], p[2], p[3]));
+  let [al, aa, ab, _] = a.convert::<Oklab>().components;
+  let [bl, ba, bb, _] = b.convert::<Oklab>().components;
+
+  let lab = ((al - bl).powi(2) + (aa - ba).powi(2) + (ab - bb).powi(2)).sqrt();
+  lab + (a.components[3] - b.components[3]).abs()
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SnapshotError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+      SnapshotError::Missing(path) => {
+        write!(f, "{}: no reference image, set {UPDATE_VAR}=1 to create it", path.display())
+      }
+      SnapshotError::Size { path, expected, actual } => write!(
+        f,
+        "{}: expected a {}x{} image, but drew {}x{}",
+        path.display(),
+        expected.0,
+        expected.1,
+        actual.0,
+        actual.1
+      ),
+      SnapshotError::Mismatch { path, pixels, max } => write!(
+        f,
+        "{}: {pixels} pixels differ, by up to {max:.3}, set {UPDATE_VAR}=1 to accept them",
+        path.display()
+      ),
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {}
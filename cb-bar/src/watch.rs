@@ -0,0 +1,200 @@
+//! Watches the config file for changes with inotify.
+
+use std::{
+  cell::Cell,
+  ffi::CString,
+  io,
+  os::{
+    fd::{AsRawFd, FromRawFd, OwnedFd},
+    unix::ffi::OsStrExt,
+  },
+  path::{Path, PathBuf},
+  rc::Rc,
+};
+
+use cb_core::{EventLoop, PostAction};
+
+pub struct Watcher {
+  changed: Rc<Cell<bool>>,
+}
+
+impl Watcher {
+  /// Watches `path` from the main loop, which notices the change on its next
+  /// iteration.
+  ///
+  /// This watches the parent directory instead of the file itself, as most
+  /// editors save by writing a new file and renaming it over the old one. If
+  /// that directory doesn't exist yet, this watches the nearest ancestor that
+  /// does, and follows the missing directories down as they're created.
+  pub fn new(path: &Path, events: &EventLoop) -> io::Result<Self> {
+    let dir = path.parent().ok_or(io::ErrorKind::InvalidInput)?.to_path_buf();
+    let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?.to_owned();
+
+    let fd = unsafe {
+      let fd = libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      OwnedFd::from_raw_fd(fd)
+    };
+
+    let mut watches = Watches { dir, wd: None };
+    watches.add(fd.as_raw_fd())?;
+
+    let changed = Rc::new(Cell::new(false));
+
+    events.add_fd(fd, {
+      let changed = changed.clone();
+      move |fd| {
+        let mut buf = [0_u8; 4096];
+
+        loop {
+          let len = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+          if len < 0 {
+            let err = io::Error::last_os_error();
+            match err.kind() {
+              io::ErrorKind::Interrupted => continue,
+              io::ErrorKind::WouldBlock => return PostAction::Continue,
+              _ => {
+                eprintln!("config watcher: {err}");
+                return PostAction::Remove;
+              }
+            }
+          }
+
+          for (wd, mask, file) in inotify_events(&buf[..len as usize]) {
+            if Some(wd) != watches.wd {
+              // A directory above the config has changed, which may have
+              // created the config directory.
+              let found = watches.wd.is_none();
+              if let Err(e) = watches.add(fd.as_raw_fd()) {
+                eprintln!("config watcher: {e}");
+              }
+              if found && watches.wd.is_some() && watches.dir.join(&name).exists() {
+                changed.set(true);
+              }
+            } else if mask & libc::IN_IGNORED != 0 {
+              // The config directory was removed, so go back to watching its
+              // ancestors.
+              watches.wd = None;
+              if let Err(e) = watches.add(fd.as_raw_fd()) {
+                eprintln!("config watcher: {e}");
+              }
+              changed.set(true);
+            } else if file == name.as_bytes() {
+              changed.set(true);
+            }
+          }
+        }
+      }
+    });
+
+    Ok(Watcher { changed })
+  }
+
+  /// Returns true if the file has changed since the last call.
+  pub fn changed(&self) -> bool { self.changed.replace(false) }
+}
+
+/// The watches on the config directory, or on its nearest ancestor while it
+/// doesn't exist.
+struct Watches {
+  dir: PathBuf,
+  /// The watch on `dir`, once it exists.
+  wd:  Option<libc::c_int>,
+}
+
+impl Watches {
+  /// Watches `dir` if it exists, and the nearest ancestor that exists
+  /// otherwise. Adding a watch to a directory that's already watched is a
+  /// no-op, so this is called again whenever an ancestor changes.
+  fn add(&mut self, fd: libc::c_int) -> io::Result<()> {
+    for dir in self.dir.ancestors() {
+      let is_dir = dir == self.dir;
+      let mask = if is_dir {
+        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_DELETE
+      } else {
+        libc::IN_CREATE | libc::IN_MOVED_TO
+      };
+
+      let path =
+        CString::new(dir.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput)?;
+      let wd = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask | libc::IN_ONLYDIR) };
+      if wd >= 0 {
+        if is_dir {
+          self.wd = Some(wd);
+        }
+        return Ok(());
+      }
+
+      let err = io::Error::last_os_error();
+      if err.kind() != io::ErrorKind::NotFound {
+        return Err(err);
+      }
+    }
+
+    Err(io::ErrorKind::NotFound.into())
+  }
+}
+
+/// Iterates over the watch descriptors, masks and file names in a buffer of
+/// `inotify_event`s.
+fn inotify_events(mut buf: &[u8]) -> impl Iterator<Item = (libc::c_int, u32, &[u8])> {
+  const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+  std::iter::from_fn(move || {
+    if buf.len() < HEADER {
+      return None;
+    }
+
+    let event = unsafe { buf.as_ptr().cast::<libc::inotify_event>().read_unaligned() };
+    let name = &buf[HEADER..HEADER + event.len as usize];
+    buf = &buf[HEADER + event.len as usize..];
+
+    // The name is padded with nul bytes.
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some((event.wd, event.mask, &name[..end]))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Runs the event loop until nothing has happened for a little while.
+  fn dispatch(events: &EventLoop) {
+    loop {
+      let mut fds = vec![];
+      events.fill_pollfds(&mut fds);
+      let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, 50) };
+      if n <= 0 {
+        break;
+      }
+      events.dispatch(&fds);
+    }
+  }
+
+  #[test]
+  fn notices_config_in_new_directory() {
+    let root = std::env::temp_dir().join(format!("cb-watch-{}", std::process::id()));
+    let path = root.join("correct-bar").join("config.toml");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+
+    let events = EventLoop::new();
+    let watcher = Watcher::new(&path, &events).unwrap();
+    dispatch(&events);
+    assert!(!watcher.changed());
+
+    std::fs::create_dir(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, "").unwrap();
+    dispatch(&events);
+    assert!(watcher.changed());
+
+    std::fs::write(&path, "[[left]]").unwrap();
+    dispatch(&events);
+    assert!(watcher.changed());
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
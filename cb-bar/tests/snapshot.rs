@@ -0,0 +1,158 @@
+//! Draws bars of stub modules, and compares them against the references in
+//! `tests/snapshots`. Run with `CB_UPDATE_SNAPSHOTS=1` to update them.
+
+use cb_bar::{
+  Axis, Bar, Layout, Module, TextLayout,
+  snapshot::{Snapshot, primary, secondary},
+};
+use cb_core::{Render, Text};
+use kurbo::Rect;
+
+fn snapshot() -> Snapshot {
+  Snapshot::fixture(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"))
+}
+
+/// Shows a fixed value and unit, like most of the builtin modules.
+struct Label {
+  value: &'static str,
+  unit:  &'static str,
+  text:  Option<TextLayout>,
+}
+
+fn label(value: &'static str, unit: &'static str) -> Box<dyn Module> {
+  Box::new(Label { value, unit, text: None })
+}
+
+impl Module for Label {
+  fn layout(&mut self, layout: &mut Layout) {
+    layout.pad(5.0);
+
+    let mut text = Text::new();
+    text.push(self.value, primary());
+    text.push(self.unit, secondary());
+    self.text = Some(layout.layout_text(text, primary()));
+
+    layout.pad(5.0);
+  }
+
+  fn render(&self, render: &mut Render) {
+    if let Some(text) = &self.text {
+      render.draw(text);
+    }
+  }
+}
+
+/// Shows some text in a button, like the workspaces of the hypr module.
+struct Button {
+  text: Option<TextLayout>,
+}
+
+fn button() -> Box<dyn Module> { Box::new(Button { text: None }) }
+
+impl Module for Button {
+  fn layout(&mut self, layout: &mut Layout) {
+    layout.pad(10.0);
+    self.text = Some(layout.layout_text("button", primary()));
+    layout.pad(10.0);
+  }
+
+  fn render(&self, render: &mut Render) {
+    if let Some(text) = &self.text {
+      render.draw(text);
+      render.draw_button(&text.bounds().inflate(4.0, 2.0), secondary());
+    }
+  }
+}
+
+/// Fills rectangles on whole pixels, so that they aren't antialiased, and the
+/// GPU and the CPU draw the same pixels.
+struct Swatches;
+
+const SWATCHES: [(f32, f32, f32, f32); 6] = [
+  (0.0, 0.0, 0.0, 1.0),
+  (1.0, 0.0, 0.0, 1.0),
+  (0.63, 0.26, 29.0, 1.0),
+  (0.87, 0.29, 142.0, 1.0),
+  (0.45, 0.31, 264.0, 1.0),
+  (0.7, 0.1, 200.0, 0.5),
+];
+
+impl Module for Swatches {
+  fn layout(&mut self, layout: &mut Layout) { layout.pad(20.0 * SWATCHES.len() as f64); }
+
+  fn render(&self, render: &mut Render) {
+    for (i, &(l, c, h, alpha)) in SWATCHES.iter().enumerate() {
+      let x = 20.0 * i as f64;
+      let color = cb_core::oklch(l, c, h).multiply_alpha(alpha);
+      render.fill(&Rect::new(x, 5.0, x + 20.0, 25.0), color);
+    }
+  }
+}
+
+#[track_caller]
+fn check(result: Result<(), cb_bar::snapshot::SnapshotError>) {
+  if let Err(e) = result {
+    panic!("{e}");
+  }
+}
+
+fn sides() -> Bar {
+  Bar {
+    left_modules:   vec![label("12", "%"), label("3.4", "G")],
+    center_modules: vec![label("56", "°")],
+    right_modules:  vec![label("7:08", "")],
+  }
+}
+
+#[test]
+fn horizontal() { check(snapshot().check_bar("horizontal", sides(), 300, 30)); }
+
+#[test]
+fn vertical() { check(snapshot().axis(Axis::Vertical).check_bar("vertical", sides(), 50, 300)); }
+
+#[test]
+fn scaled() { check(snapshot().scale(2.0).check_bar("scaled", sides(), 300, 30)); }
+
+#[test]
+fn button_without_cursor() { check(snapshot().check_module("button", button(), 100, 30)); }
+
+#[test]
+fn button_under_cursor() {
+  let snapshot = snapshot().cursor(Some((20.0, 12.0)));
+  check(snapshot.check_module("button_hover", button(), 100, 30));
+}
+
+#[test]
+fn button_near_cursor() {
+  let snapshot = snapshot().cursor(Some((40.0, 28.0)));
+  check(snapshot.check_module("button_near", button(), 100, 30));
+}
+
+#[test]
+fn swatches() { check(snapshot().check_module("swatches", Box::new(Swatches), 120, 30)); }
+
+/// Checks the colors the GPU draws, which go through `encode_color` and the
+/// blit shader, against the colors of the CPU. This is skipped without a GPU
+/// or software adapter.
+#[test]
+fn swatches_on_gpu() {
+  let snapshot = snapshot();
+  let bar = || Bar { left_modules: vec![Box::new(Swatches)], ..Default::default() };
+  let Some(gpu) = snapshot.render_gpu(bar(), 120, 30) else {
+    eprintln!("no GPU, skipping");
+    return;
+  };
+  let cpu = snapshot.render(bar(), 120, 30);
+
+  assert_eq!((gpu.width, gpu.height), (cpu.width, cpu.height));
+  for (i, (a, b)) in gpu.pixels.chunks_exact(4).zip(cpu.pixels.chunks_exact(4)).enumerate() {
+    // Allow for rounding, and dithering on the GPU. Colors are compared
+    // premultiplied, as translucent colors lose precision when straightened.
+    let premultiply = |p: &[u8]| {
+      let [r, g, b, a] = [p[0], p[1], p[2], p[3]].map(u16::from);
+      [r * a / 255, g * a / 255, b * a / 255, a]
+    };
+    let close = premultiply(a).iter().zip(premultiply(b)).all(|(a, b)| a.abs_diff(b) <= 3);
+    assert!(close, "pixel {i} is {a:?} on the GPU, and {b:?} on the CPU");
+  }
+}
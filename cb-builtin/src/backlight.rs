@@ -0,0 +1,386 @@
+//! Shows the brightness of a backlight in `/sys/class/backlight`, or of a
+//! keyboard backlight in `/sys/class/leds`.
+//!
+//! Scrolling sets the brightness. Devices the user can't write to are set
+//! through logind, which needs `busctl` from systemd.
+
+use std::{
+  cell::RefCell,
+  ffi::CString,
+  fs, io,
+  os::{
+    fd::{AsRawFd, FromRawFd, OwnedFd},
+    unix::ffi::OsStrExt,
+  },
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  rc::{Rc, Weak},
+  sync::{LazyLock, atomic::Ordering, mpsc},
+};
+
+use cb_bar::{Axis, Module, TextLayout, Updater};
+use cb_core::{Color, EventLoop, PostAction, Render, Scroll, Text};
+use kurbo::Point;
+use parking_lot::Mutex;
+
+use crate::{Dirty, UpdateGroup};
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backlight {
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub secondary: Color,
+  /// Which backlight to show, if `device` isn't set.
+  #[serde(default)]
+  pub target:    Target,
+  /// The backlight to show, like `"intel_backlight"`, or a led like
+  /// `"tpacpi::kbd_backlight"`.
+  #[serde(default)]
+  pub device:    Option<String>,
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+  /// The backlight of the screen.
+  #[default]
+  Screen,
+  /// The first led named like `*kbd_backlight*`.
+  Keyboard,
+}
+
+/// How much one scroll wheel click changes the brightness, in percent.
+const SCROLL_STEP: f64 = 5.0;
+
+/// A backlight, and the subsystem it belongs to, which logind needs to set
+/// its brightness.
+#[derive(Clone, Debug)]
+struct Device {
+  subsystem: &'static str,
+  name:      String,
+  path:      PathBuf,
+}
+
+struct BacklightModule {
+  spec:       Backlight,
+  axis:       Axis,
+  text:       Option<TextLayout>,
+  dirty:      Dirty,
+  device:     Option<Device>,
+  brightness: u32,
+  max:        u32,
+  /// The brightness last set by scrolling, until the device reports it.
+  target:     Option<u32>,
+  /// Scroll distance that hasn't added up to a whole step yet.
+  scroll:     f64,
+}
+
+impl From<Backlight> for Box<dyn Module> {
+  fn from(spec: Backlight) -> Self {
+    Box::new(BacklightModule {
+      spec,
+      axis: Axis::Horizontal,
+      text: None,
+      dirty: UPDATERS.lock().add(),
+      device: None,
+      brightness: 0,
+      max: 0,
+      target: None,
+      scroll: 0.0,
+    })
+  }
+}
+
+static UPDATERS: Mutex<UpdateGroup> = Mutex::new(UpdateGroup::new());
+
+impl Device {
+  /// Finds the device picked by `spec`. Named devices are looked up as
+  /// backlights first, then as leds.
+  fn find(spec: &Backlight) -> Option<Device> {
+    let device = |subsystem, name: &str| {
+      let path = crate::system_path("/sys/class").join(subsystem).join(name);
+      path.join("brightness").exists().then(|| Device { subsystem, name: name.to_string(), path })
+    };
+
+    if let Some(name) = &spec.device {
+      return device("backlight", name).or_else(|| device("leds", name));
+    }
+
+    match spec.target {
+      Target::Screen => {
+        let backlights = crate::system_path("/sys/class/backlight");
+
+        // Prefer the backlights the kernel recommends, which are the ones that
+        // know how the panel actually responds.
+        let priority = |name: &String| {
+          let kind = fs::read_to_string(backlights.join(name).join("type"));
+          match kind.as_deref().map(str::trim) {
+            Ok("firmware") => 0,
+            Ok("platform") => 1,
+            _ => 2,
+          }
+        };
+
+        let mut names = list(&backlights);
+        names.sort_by_cached_key(|name| (priority(name), name.clone()));
+        names.iter().find_map(|name| device("backlight", name))
+      }
+      Target::Keyboard => list(&crate::system_path("/sys/class/leds"))
+        .iter()
+        .filter(|name| name.contains("kbd_backlight"))
+        .find_map(|name| device("leds", name)),
+    }
+  }
+
+  /// Reads the current and maximum brightness.
+  fn read(&self) -> Option<(u32, u32)> {
+    let read = |name| fs::read_to_string(self.path.join(name)).ok()?.trim().parse().ok();
+    Some((read("brightness")?, read("max_brightness")?))
+  }
+
+  /// Sets the brightness by writing to the device directly, which works when
+  /// the user is allowed to, and through logind otherwise.
+  fn set(&self, brightness: u32) {
+    match fs::write(self.path.join("brightness"), brightness.to_string()) {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+        let _ = LOGIND.send((self.clone(), brightness));
+      }
+      Err(e) => eprintln!("backlight: cannot set brightness of {}: {e}", self.name),
+    }
+  }
+}
+
+/// Returns the sorted names of the entries in `dir`.
+fn list(dir: &Path) -> Vec<String> {
+  let mut names = fs::read_dir(dir)
+    .into_iter()
+    .flatten()
+    .flatten()
+    .filter_map(|entry| entry.file_name().into_string().ok())
+    .collect::<Vec<_>>();
+  names.sort_unstable();
+  names
+}
+
+/// Sets brightness through logind's `SetBrightness`, which lets the user of
+/// the active session change it without write access to the device. This
+/// calls `busctl` from a separate thread, so the bar doesn't wait on it.
+///
+/// `busctl` comes with systemd, so without it, brightness can only be set by
+/// users who can write to the device.
+static LOGIND: LazyLock<mpsc::Sender<(Device, u32)>> = LazyLock::new(|| {
+  let (tx, rx) = mpsc::channel::<(Device, u32)>();
+
+  std::thread::spawn(move || {
+    let mut missing = false;
+    while let Ok(mut request) = rx.recv() {
+      // Skip to the latest brightness when scrolling faster than logind
+      // keeps up.
+      while let Ok(next) = rx.try_recv() {
+        request = next;
+      }
+
+      let (device, brightness) = request;
+      let status = Command::new("busctl")
+        .args([
+          "call",
+          "org.freedesktop.login1",
+          "/org/freedesktop/login1/session/auto",
+          "org.freedesktop.login1.Session",
+          "SetBrightness",
+          "ssu",
+          device.subsystem,
+          &device.name,
+          &brightness.to_string(),
+        ])
+        .stdout(Stdio::null())
+        .status();
+
+      match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("backlight: cannot set brightness of {}: {status}", device.name),
+        // Don't repeat this on every scroll.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+          if !missing {
+            eprintln!(
+              "backlight: cannot set brightness of {} without write access to it, or busctl",
+              device.name,
+            );
+            missing = true;
+          }
+        }
+        Err(e) => eprintln!("backlight: cannot run busctl: {e}"),
+      }
+    }
+  });
+
+  tx
+});
+
+/// The inotify instance that watches every backlight, once it's been added
+/// to a main loop. The loop owns it, so it's closed along with the loop, or
+/// once reading from it fails.
+#[derive(Default)]
+struct Inotify(RefCell<Weak<OwnedFd>>);
+
+/// Starts watching `device` for changes from the main loop.
+///
+/// This watches the device directory, as the kernel reports changes made with
+/// brightness keys on `actual_brightness` or `brightness_hw_changed`, rather
+/// than on `brightness`.
+fn watch(device: &Device, events: &EventLoop) -> io::Result<()> {
+  let inotify = events.data::<Inotify>();
+  let existing = inotify.0.borrow().upgrade();
+  let fd = match existing {
+    Some(fd) => fd,
+    None => {
+      let fd = unsafe {
+        let fd = libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK);
+        if fd < 0 {
+          return Err(io::Error::last_os_error());
+        }
+        Rc::new(OwnedFd::from_raw_fd(fd))
+      };
+      *inotify.0.borrow_mut() = Rc::downgrade(&fd);
+
+      events.add_fd(fd.clone(), |fd| {
+        let mut buf = [0_u8; 4096];
+
+        loop {
+          let len = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+          if len < 0 {
+            let err = io::Error::last_os_error();
+            match err.kind() {
+              io::ErrorKind::Interrupted => continue,
+              io::ErrorKind::WouldBlock => return PostAction::Continue,
+              _ => {
+                eprintln!("backlight: {err}");
+                return PostAction::Remove;
+              }
+            }
+          }
+
+          // Every file of a backlight is about its brightness, so there's no
+          // need to look at the events.
+          if len > 0 {
+            UPDATERS.lock().mark_dirty();
+          }
+        }
+      });
+
+      fd
+    }
+  };
+
+  let path =
+    CString::new(device.path.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput)?;
+  // Watching the same path again reuses the existing watch.
+  let wd = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), libc::IN_MODIFY) };
+  if wd < 0 {
+    return Err(io::Error::last_os_error());
+  }
+
+  Ok(())
+}
+
+impl BacklightModule {
+  /// Returns the brightness to show, which is the one last set by scrolling
+  /// until the device reports it.
+  fn shown(&self) -> u32 { self.target.unwrap_or(self.brightness) }
+}
+
+impl Module for BacklightModule {
+  fn updater(&self) -> Updater<'_> { Updater::Atomic(self.dirty.get()) }
+
+  fn on_scroll(&mut self, _: Point, scroll: Scroll) {
+    let Some(device) = &self.device else { return };
+    if self.max == 0 {
+      return;
+    }
+
+    // Scrolling up raises the brightness.
+    self.scroll -= scroll.steps_y();
+    let steps = self.scroll.trunc();
+    if steps == 0.0 {
+      return;
+    }
+    self.scroll -= steps;
+
+    // Keyboard backlights often only have a few levels, so always move by at
+    // least one.
+    let step = (self.max as f64 * SCROLL_STEP / 100.0).round().max(1.0);
+    // Turning the screen off completely is rarely what scrolling down meant.
+    let min = if device.subsystem == "backlight" { 1 } else { 0 };
+
+    let current = self.shown();
+    let brightness = (current as f64 + steps * step).clamp(min as f64, self.max as f64) as u32;
+    if brightness != current {
+      self.target = Some(brightness);
+      device.set(brightness);
+      // Leds don't report brightness they're set to, and logind takes a
+      // while, so show the new brightness right away.
+      self.dirty.get().store(true, Ordering::SeqCst);
+    }
+  }
+
+  fn tooltip(&self) -> Option<Text<'static>> {
+    let device = self.device.as_ref()?;
+
+    let mut text = Text::new();
+    text.push(format_args!("{}: ", device.name), self.spec.secondary);
+    text.push(format_args!("{}", self.shown()), self.spec.primary);
+    text.push(format_args!(" / {}", self.max), self.spec.secondary);
+    Some(text)
+  }
+
+  fn layout(&mut self, layout: &mut cb_bar::Layout) {
+    layout.pad(10.0);
+
+    self.dirty.clear();
+    self.axis = layout.axis();
+
+    if self.device.is_none() {
+      self.device = Device::find(&self.spec);
+      if let Some(device) = &self.device
+        && let Err(e) = watch(device, layout.events)
+      {
+        eprintln!("backlight: cannot watch {}: {e}", device.name);
+      }
+    }
+
+    let previous = self.brightness;
+    let reading = self.device.as_ref().and_then(Device::read);
+    (self.brightness, self.max) = reading.unwrap_or((0, 0));
+    // Once the device reports a change, scrolling continues from there, so a
+    // brightness that never got set doesn't stick around.
+    if self.brightness != previous || self.target == Some(self.brightness) {
+      self.target = None;
+    }
+
+    let mut text = Text::new();
+    if self.max == 0 {
+      // Keep the module in place, so it's clear the backlight is missing.
+      text.push("--", self.spec.secondary);
+    } else {
+      let percent = self.shown() as f64 / self.max as f64 * 100.0;
+      text.push(format_args!("{percent:.0}"), self.spec.primary);
+    }
+    text.push("%", self.spec.secondary);
+
+    self.text = Some(layout.layout_text(text, self.spec.primary));
+
+    layout.pad(5.0);
+  }
+
+  fn render(&self, ctx: &mut Render) {
+    if let Some(text) = &self.text {
+      ctx.draw(text);
+
+      let fract = if self.max == 0 { 0.0 } else { self.shown() as f64 / self.max as f64 };
+      crate::draw_level(ctx, text, self.axis, fract, self.spec.secondary, self.spec.primary);
+    }
+  }
+}
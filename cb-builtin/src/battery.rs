@@ -0,0 +1,439 @@
+//! Shows the charge of the batteries in `/sys/class/power_supply`.
+
+use std::{
+  cell::Cell,
+  fs, io,
+  os::fd::{AsRawFd, FromRawFd, OwnedFd},
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use cb_bar::{Axis, Module, TextLayout, Updater};
+use cb_core::{Color, EventLoop, PostAction, Render, Text};
+use parking_lot::Mutex;
+
+use crate::{Dirty, UpdateGroup};
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Battery {
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub secondary: Color,
+  /// The batteries to show, like `"BAT0"`. If this is empty, every battery is
+  /// shown, combined into one.
+  #[serde(default)]
+  pub batteries: Vec<String>,
+}
+
+/// How often batteries are read, on top of the changes the kernel reports.
+/// Most batteries don't report their charge going down.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Estimates longer than this are left out, as they're mostly noise from a
+/// tiny power draw.
+const MAX_ESTIMATE: Duration = Duration::from_secs(48 * 60 * 60);
+
+/// The value of the `status` file of a battery.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum State {
+  #[default]
+  Unknown,
+  Charging,
+  Discharging,
+  NotCharging,
+  Full,
+}
+
+/// A battery, with energy in watt-hours, and power in watts.
+#[derive(Clone, Debug)]
+struct Supply {
+  name:        String,
+  state:       State,
+  /// The charge, from 0 to 100.
+  capacity:    Option<f64>,
+  energy_now:  Option<f64>,
+  energy_full: Option<f64>,
+  power:       Option<f64>,
+}
+
+/// The state of all the batteries that are shown.
+#[derive(Clone, Debug, Default)]
+struct Status {
+  batteries: Vec<Supply>,
+  /// Set if an AC adapter is plugged in.
+  ac:        bool,
+
+  /// The combined charge, from 0 to 100.
+  capacity: f64,
+  state:    State,
+  /// The time until the batteries are empty while discharging, or full while
+  /// charging.
+  time:     Option<Duration>,
+}
+
+struct BatteryModule {
+  spec:   Battery,
+  axis:   Axis,
+  text:   Option<TextLayout>,
+  dirty:  Dirty,
+  status: Status,
+}
+
+impl From<Battery> for Box<dyn Module> {
+  fn from(spec: Battery) -> Self {
+    Box::new(BatteryModule {
+      spec,
+      axis: Axis::Horizontal,
+      text: None,
+      dirty: UPDATERS.lock().add(),
+      status: Status::default(),
+    })
+  }
+}
+
+static UPDATERS: Mutex<UpdateGroup> = Mutex::new(UpdateGroup::new());
+
+impl State {
+  fn parse(s: &str) -> Self {
+    match s {
+      "Charging" => State::Charging,
+      "Discharging" => State::Discharging,
+      "Not charging" => State::NotCharging,
+      "Full" => State::Full,
+      _ => State::Unknown,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      State::Unknown => "unknown",
+      State::Charging => "charging",
+      State::Discharging => "discharging",
+      State::NotCharging => "not charging",
+      State::Full => "full",
+    }
+  }
+}
+
+impl Supply {
+  /// Reads the battery at `path`. Batteries either report energy and power
+  /// directly, or charge and current, which are multiplied by the voltage.
+  fn read(path: &Path) -> Option<Supply> {
+    let read = |name: &str| Some(fs::read_to_string(path.join(name)).ok()?.trim().to_string());
+    // Values are in micro units, like µWh.
+    let num = |name: &str| Some(read(name)?.parse::<f64>().ok()? / 1_000_000.0);
+
+    let voltage = num("voltage_min_design").or_else(|| num("voltage_now"));
+    let from_charge = |name: &str| Some(num(name)? * voltage?);
+
+    Some(Supply {
+      name:        path.file_name()?.to_str()?.to_string(),
+      state:       State::parse(&read("status")?),
+      capacity:    read("capacity").and_then(|c| c.parse().ok()),
+      energy_now:  num("energy_now").or_else(|| from_charge("charge_now")),
+      energy_full: num("energy_full").or_else(|| from_charge("charge_full")),
+      // Some batteries report a negative draw while discharging.
+      power:       num("power_now").or_else(|| from_charge("current_now")).map(f64::abs),
+    })
+  }
+}
+
+impl Status {
+  /// Reads every power supply, and combines the batteries named in `names`,
+  /// or all of them if `names` is empty.
+  fn read(names: &[String]) -> Status {
+    let mut status = Status::default();
+
+    let Ok(dir) = fs::read_dir(crate::system_path("/sys/class/power_supply")) else { return status };
+    for entry in dir.flatten() {
+      let path = entry.path();
+      let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+
+      match kind.trim() {
+        // USB-C chargers show up as USB supplies, which are online while they
+        // provide power.
+        "Mains" | "USB" => {
+          status.ac |= fs::read_to_string(path.join("online")).is_ok_and(|s| s.trim() == "1");
+        }
+        "Battery" => {
+          // Leave out the batteries of devices, like mice.
+          if fs::read_to_string(path.join("scope")).is_ok_and(|s| s.trim() == "Device") {
+            continue;
+          }
+          let Some(supply) = Supply::read(&path) else { continue };
+          if names.is_empty() || names.contains(&supply.name) {
+            status.batteries.push(supply);
+          }
+        }
+        _ => {}
+      }
+    }
+    status.batteries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    status.combine();
+    status
+  }
+
+  fn combine(&mut self) {
+    let batteries = &self.batteries;
+    let total = |f: fn(&Supply) -> Option<f64>| batteries.iter().map(f).sum::<Option<f64>>();
+    let (now, full, power) =
+      (total(|b| b.energy_now), total(|b| b.energy_full), total(|b| b.power));
+
+    self.capacity = match (now, full) {
+      (Some(now), Some(full)) if full > 0.0 => now / full * 100.0,
+      _ => {
+        let capacities = batteries.iter().filter_map(|b| b.capacity).collect::<Vec<_>>();
+        capacities.iter().sum::<f64>() / capacities.len().max(1) as f64
+      }
+    }
+    .clamp(0.0, 100.0);
+
+    let any = |state| batteries.iter().any(|b| b.state == state);
+    self.state = if any(State::Charging) {
+      State::Charging
+    } else if any(State::Discharging) {
+      State::Discharging
+    } else if !batteries.is_empty() && batteries.iter().all(|b| b.state == State::Full) {
+      State::Full
+    } else if any(State::NotCharging) {
+      State::NotCharging
+    } else {
+      State::Unknown
+    };
+
+    let hours = match (self.state, now, full, power) {
+      (State::Charging, Some(now), Some(full), Some(power)) if power > 0.0 => (full - now) / power,
+      (State::Discharging, Some(now), _, Some(power)) if power > 0.0 => now / power,
+      _ => f64::NAN,
+    };
+    self.time = Duration::try_from_secs_f64(hours * 3600.0).ok().filter(|&t| t <= MAX_ESTIMATE);
+  }
+}
+
+/// Formats `time` like `1:05`.
+fn format_time(time: Duration) -> String {
+  let minutes = time.as_secs() / 60;
+  format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Opens a socket that receives the uevents of the kernel, which are sent
+/// whenever a device changes, like an AC adapter being plugged in.
+fn uevent_socket() -> io::Result<OwnedFd> {
+  unsafe {
+    let fd = libc::socket(
+      libc::AF_NETLINK,
+      libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+      libc::NETLINK_KOBJECT_UEVENT,
+    );
+    if fd < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let fd = OwnedFd::from_raw_fd(fd);
+
+    let mut addr = std::mem::zeroed::<libc::sockaddr_nl>();
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    // The group of uevents sent by the kernel itself.
+    addr.nl_groups = 1;
+    let len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+    if libc::bind(fd.as_raw_fd(), (&raw const addr).cast(), len) < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(fd)
+  }
+}
+
+/// Starts watching for changes to power supplies from the main loop, if that
+/// isn't happening already.
+fn listen(events: &EventLoop) {
+  thread_local! {
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+  }
+
+  if LISTENING.replace(true) {
+    return;
+  }
+
+  match uevent_socket() {
+    Ok(socket) => events.add_fd(socket, |socket| {
+      let mut buf = [0_u8; 8192];
+      let mut changed = false;
+      loop {
+        let n = unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if n <= 0 {
+          break;
+        }
+        // Uevents are a list of `KEY=value` strings, separated by NUL bytes.
+        changed |= buf[..n as usize].split(|&b| b == 0).any(|s| s == b"SUBSYSTEM=power_supply");
+      }
+
+      if changed {
+        UPDATERS.lock().mark_dirty();
+      }
+      PostAction::Continue
+    }),
+    Err(e) => eprintln!("battery: cannot watch power supplies: {e}"),
+  }
+
+  events.add_timer(Instant::now() + POLL_INTERVAL, || {
+    UPDATERS.lock().mark_dirty();
+    Some(Instant::now() + POLL_INTERVAL)
+  });
+}
+
+impl Module for BatteryModule {
+  fn updater(&self) -> Updater<'_> { Updater::Atomic(self.dirty.get()) }
+
+  fn tooltip(&self) -> Option<Text<'static>> {
+    let status = &self.status;
+
+    let mut text = Text::new();
+    for battery in &status.batteries {
+      text.push(format_args!("{}: ", battery.name), self.spec.secondary);
+      if let Some(capacity) = battery.capacity {
+        text.push(format_args!("{capacity:.0}"), self.spec.primary);
+        text.push("%, ", self.spec.secondary);
+      }
+      text.push(battery.state.name(), self.spec.primary);
+      text.push("\n", self.spec.secondary);
+    }
+
+    text.push(if status.ac { "plugged in" } else { "on battery" }, self.spec.primary);
+    if let Some(time) = status.time {
+      let until = if status.state == State::Charging { "full" } else { "empty" };
+      text.push(", ", self.spec.secondary);
+      text.push(format_time(time), self.spec.primary);
+      text.push(format_args!(" until {until}"), self.spec.secondary);
+    }
+    Some(text)
+  }
+
+  fn layout(&mut self, layout: &mut cb_bar::Layout) {
+    layout.pad(10.0);
+
+    listen(layout.events);
+    self.dirty.clear();
+
+    self.status = Status::read(&self.spec.batteries);
+    self.axis = layout.axis();
+
+    let mut text = Text::new();
+    if self.status.batteries.is_empty() {
+      // Keep the module in place, so it's clear the battery is missing.
+      text.push("--", self.spec.secondary);
+    } else {
+      text.push(format_args!("{:.0}", self.status.capacity), self.spec.primary);
+    }
+    text.push("%", self.spec.secondary);
+
+    let sign = match self.status.state {
+      State::Charging => Some("+"),
+      State::Discharging => Some("-"),
+      _ => None,
+    };
+    if let (Some(sign), Some(time)) = (sign, self.status.time) {
+      text.push(format_args!(" {sign}"), self.spec.secondary);
+      text.push(format_time(time), self.spec.primary);
+    }
+
+    self.text = Some(layout.layout_text(text, self.spec.primary));
+
+    layout.pad(5.0);
+  }
+
+  fn render(&self, ctx: &mut Render) {
+    if let Some(text) = &self.text {
+      ctx.draw(text);
+
+      let fract = self.status.capacity / 100.0;
+      crate::draw_level(ctx, text, self.axis, fract, self.spec.secondary, self.spec.primary);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn supply(state: State, capacity: f64, energy: Option<(f64, f64)>, power: f64) -> Supply {
+    Supply {
+      name: "BAT0".into(),
+      state,
+      capacity: Some(capacity),
+      energy_now: energy.map(|(now, _)| now),
+      energy_full: energy.map(|(_, full)| full),
+      power: Some(power),
+    }
+  }
+
+  fn combine(batteries: Vec<Supply>) -> Status {
+    let mut status = Status { batteries, ..Default::default() };
+    status.combine();
+    status
+  }
+
+  #[test]
+  fn combines_energy() {
+    let status = combine(vec![
+      supply(State::Discharging, 50.0, Some((20.0, 40.0)), 5.0),
+      supply(State::Full, 100.0, Some((20.0, 20.0)), 0.0),
+    ]);
+    // 40 of 60 Wh, which lasts 8 hours at 5 W.
+    assert!((status.capacity - 66.667).abs() < 0.001, "{}", status.capacity);
+    assert_eq!(status.state, State::Discharging);
+    assert_eq!(status.time, Some(Duration::from_secs(8 * 3600)));
+  }
+
+  #[test]
+  fn averages_capacity_without_energy() {
+    let status = combine(vec![
+      supply(State::Charging, 40.0, None, 5.0),
+      supply(State::NotCharging, 80.0, None, 0.0),
+    ]);
+    assert_eq!(status.capacity, 60.0);
+    assert_eq!(status.state, State::Charging);
+    assert_eq!(status.time, None);
+  }
+
+  #[test]
+  fn estimates_time_until_full() {
+    let status = combine(vec![supply(State::Charging, 50.0, Some((25.0, 50.0)), 10.0)]);
+    assert_eq!(status.time, Some(Duration::from_secs(9000)));
+
+    // Tiny draws give estimates that are too long to mean anything.
+    let status = combine(vec![supply(State::Discharging, 50.0, Some((25.0, 50.0)), 0.1)]);
+    assert_eq!(status.time, None);
+
+    let status = combine(vec![supply(State::Discharging, 50.0, Some((25.0, 50.0)), 0.0)]);
+    assert_eq!(status.time, None);
+  }
+
+  #[test]
+  fn picks_state() {
+    let state = |states: &[State]| {
+      combine(states.iter().map(|&state| supply(state, 50.0, None, 0.0)).collect()).state
+    };
+    assert_eq!(state(&[State::Full, State::Full]), State::Full);
+    assert_eq!(state(&[State::Full, State::NotCharging]), State::NotCharging);
+    assert_eq!(state(&[State::Discharging, State::Charging]), State::Charging);
+    assert_eq!(state(&[State::Full, State::Unknown]), State::Unknown);
+    assert_eq!(state(&[]), State::Unknown);
+    assert_eq!(combine(vec![]).capacity, 0.0);
+  }
+
+  #[test]
+  fn reads_power_supplies() {
+    crate::set_system_root(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/root"));
+
+    // The mouse battery is left out, and the USB-C charger counts as AC.
+    let status = Status::read(&[]);
+    let names = status.batteries.iter().map(|b| b.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["BAT0"]);
+    assert!(status.ac);
+
+    assert!(Status::read(&["BAT1".into()]).batteries.is_empty());
+  }
+}
@@ -1,41 +1,67 @@
-use cb_bar::{Module, TextLayout};
-use cb_core::{Color, Text};
-use chrono::{Datelike, Timelike};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-#[derive(Clone)]
+use cb_bar::{Axis, Module, Popup, TextLayout};
+use cb_core::{Click, Color, MouseButton, Scroll, Text};
+use chrono::{Datelike, Months, NaiveDate, Timelike};
+use kurbo::{Point, Size};
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Clock {
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub secondary: Color,
+  /// Extra time zones to show in the tooltip, like `"America/New_York"`.
+  #[serde(default)]
+  pub timezones: Vec<chrono_tz::Tz>,
 }
 
+/// The format of each date in the tooltip.
+const TOOLTIP_FORMAT: &str = "%A, %B %-d %Y, %H:%M:%S";
+
 struct ClockModule {
   spec: Clock,
   text: Option<TextLayout>,
+  /// The start of the next second, when the text needs to change.
+  next: std::time::Instant,
+
+  open_calendar: bool,
 }
 
 impl From<Clock> for Box<dyn Module> {
-  fn from(spec: Clock) -> Self { Box::new(ClockModule { spec, text: None }) }
+  fn from(spec: Clock) -> Self { Box::new(ClockModule {
+      spec,
+      text: None,
+      next: std::time::Instant::now(),
+      open_calendar: false,
+    }) }
 }
 
 impl Module for ClockModule {
-  fn updater(&self) -> cb_bar::Updater<'_> {
-    cb_bar::Updater::Every(std::time::Duration::from_secs(1))
-  }
+  fn updater(&self) -> cb_bar::Updater<'_> { cb_bar::Updater::At(self.next) }
 
   fn layout(&mut self, layout: &mut cb_bar::Layout) {
     layout.pad(5.0);
 
+    self.next = cb_bar::next_tick(std::time::Duration::from_secs(1));
+
     let local = chrono::Local::now();
     let utc = local.naive_utc();
 
+    // A vertical bar is too narrow for the whole line, so put each part on its
+    // own line instead.
+    let vertical = layout.axis() == Axis::Vertical;
+    let sep = |s| if vertical { "\n" } else { s };
+
     let mut text = Text::new();
     text.push(&local.weekday().to_string(), Color::WHITE);
-    text.push(", ", self.spec.secondary);
+    text.push(sep(", "), self.spec.secondary);
     text.push(
       &format_args!("{:04}-{:02}-{:02}", local.year(), local.month(), local.day()),
       Color::WHITE,
     );
-    text.push(" at ", self.spec.secondary);
+    text.push(sep(" at "), self.spec.secondary);
 
     macro_rules! draw_time {
       ( $time:expr ) => {
@@ -47,7 +73,7 @@ impl Module for ClockModule {
     }
 
     draw_time!(local);
-    text.push(" or ", self.spec.secondary);
+    text.push(sep(" or "), self.spec.secondary);
     draw_time!(utc);
 
     self.text = Some(layout.layout_text(text, self.spec.secondary));
@@ -55,6 +81,37 @@ impl Module for ClockModule {
     layout.pad(5.0);
   }
 
+  fn tooltip(&self) -> Option<Text<'static>> {
+    let now = chrono::Utc::now();
+
+    let mut text = Text::new();
+    text.push(now.with_timezone(&chrono::Local).format(TOOLTIP_FORMAT), Color::WHITE);
+    text.push("\nUTC: ", self.spec.secondary);
+    text.push(now.format(TOOLTIP_FORMAT), Color::WHITE);
+    for tz in &self.spec.timezones {
+      text.push(format_args!("\n{tz}: "), self.spec.secondary);
+      text.push(now.with_timezone(tz).format(TOOLTIP_FORMAT), Color::WHITE);
+    }
+    Some(text)
+  }
+
+  fn on_click(&mut self, _: Point, click: Click) {
+    if click.is_press(MouseButton::Left) {
+      self.open_calendar = true;
+    }
+  }
+
+  fn popup(&mut self) -> Option<Popup> {
+    if !std::mem::take(&mut self.open_calendar) {
+      return None;
+    }
+
+    Some(Popup {
+      size:    Calendar::SIZE,
+      content: Box::new(Calendar::new(self.spec.clone())),
+    })
+  }
+
   fn render(&self, ctx: &mut cb_core::Render) {
     if let Some(text) = &self.text {
       ctx.draw(text);
@@ -90,3 +147,116 @@ impl Module for ClockModule {
     */
   }
 }
+
+/// A calendar for the current month, shown in a popup under the clock.
+/// Scrolling changes the month.
+struct Calendar {
+  spec:  Clock,
+  /// The first day of the month shown.
+  month:  NaiveDate,
+  dirty:  AtomicBool,
+  /// Scroll distance that hasn't added up to a whole month yet.
+  scroll: f64,
+
+  texts: Vec<TextLayout>,
+  today: Option<TextLayout>,
+}
+
+impl Calendar {
+  const PAD: f64 = 10.0;
+  const CELL: Size = Size::new(28.0, 22.0);
+  /// Room for the title, the weekdays, and up to 6 weeks.
+  const SIZE: Size = Size::new(
+    Self::PAD * 2.0 + Self::CELL.width * 7.0,
+    Self::PAD * 2.0 + Self::CELL.height * 8.0,
+  );
+
+  fn new(spec: Clock) -> Self {
+    let today = chrono::Local::now().date_naive();
+
+    Calendar {
+      spec,
+      month: today.with_day(1).unwrap(),
+      dirty: AtomicBool::new(true),
+      scroll: 0.0,
+      texts: vec![],
+      today: None,
+    }
+  }
+
+  fn cell(column: u32, row: u32) -> Point {
+    Point::new(
+      Calendar::PAD + f64::from(column) * Calendar::CELL.width,
+      Calendar::PAD + f64::from(row) * Calendar::CELL.height,
+    )
+  }
+}
+
+impl Module for Calendar {
+  fn updater(&self) -> cb_bar::Updater<'_> { cb_bar::Updater::Atomic(&self.dirty) }
+
+  fn on_scroll(&mut self, _: Point, scroll: Scroll) {
+    self.scroll += scroll.steps_y();
+    let months = self.scroll.trunc();
+    if months == 0.0 {
+      return;
+    }
+    self.scroll -= months;
+
+    let months = months as i32;
+    let month = if months > 0 {
+      self.month.checked_add_months(Months::new(months as u32))
+    } else {
+      self.month.checked_sub_months(Months::new(months.unsigned_abs()))
+    };
+
+    if let Some(month) = month
+      && month != self.month
+    {
+      self.month = month;
+      self.dirty.store(true, Ordering::SeqCst);
+    }
+  }
+
+  fn layout(&mut self, layout: &mut cb_bar::Layout) {
+    self.dirty.store(false, Ordering::SeqCst);
+    self.texts.clear();
+    self.today = None;
+
+    let title = self.month.format("%B %Y").to_string();
+    self.texts.push(layout.layout_text_at(Calendar::cell(0, 0), &title, Color::WHITE));
+
+    for (i, day) in ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"].into_iter().enumerate() {
+      self.texts.push(layout.layout_text_at(
+        Calendar::cell(i as u32, 1),
+        day,
+        self.spec.secondary,
+      ));
+    }
+
+    let today = chrono::Local::now().date_naive();
+    let offset = self.month.weekday().num_days_from_monday();
+    for date in self.month.iter_days().take_while(|d| d.month() == self.month.month()) {
+      let index = offset + date.day0();
+      let origin = Calendar::cell(index % 7, 2 + index / 7);
+
+      let text = layout.layout_text_at(origin, &format!("{:>2}", date.day()), Color::WHITE);
+      if date == today {
+        self.today = Some(text);
+      } else {
+        self.texts.push(text);
+      }
+    }
+  }
+
+  fn render(&self, ctx: &mut cb_core::Render) {
+    for text in &self.texts {
+      ctx.draw(text);
+    }
+
+    if let Some(today) = &self.today {
+      ctx.draw_button(&today.bounds().inflate(4.0, 2.0), self.spec.primary);
+      ctx.draw(today);
+    }
+  }
+}
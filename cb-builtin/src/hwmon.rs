@@ -1,115 +1,701 @@
-//! Reads values in `/sys/class/hwmon` to get the temperature of various
-//! devices.
+//! Reads values in `/sys/class/hwmon` to get the temperature, fan speed, power
+//! draw, voltage and current of various devices, and the energy counters in
+//! `/sys/class/powercap` to get the power draw of the CPU.
 
 use std::{
   cell::RefCell,
   fs,
   fs::File,
-  io::{Read, Seek, SeekFrom},
-  path::Path,
-  time::Duration,
+  io::{self, Read, Seek, SeekFrom},
+  path::{Path, PathBuf},
+  time::{Duration, Instant},
 };
 
-use cb_bar::{Layout, Module, TextLayout, Updater};
+use cb_bar::{Animation, Layout, Module, TextLayout, Updater};
 use cb_core::{Color, Render, Text};
+use kurbo::RoundedRect;
+use peniko::color::{HueDirection, Oklab, Oklch};
 
 #[derive(Debug)]
 struct Monitor {
   // The value of the `name` file.
   name: String,
+  /// The hwmon directory, with symlinks resolved, so that it's inside the
+  /// directory of its device.
+  path: PathBuf,
 
-  temps: Vec<(String, File)>,
+  sensors: Vec<Sensor>,
+}
+
+#[derive(Debug)]
+struct Sensor {
+  kind:  Kind,
+  /// The value of the `label` file, or the name of the input, like `fan1`, if
+  /// there isn't one.
+  label: String,
+  /// The `input` file, which `file` is opened from.
+  path:  PathBuf,
+  file:  File,
+
+  /// The `max` and `crit` thresholds of this sensor, in the unit of `kind`.
+  max:  Option<f32>,
+  crit: Option<f32>,
+}
+
+/// What a sensor measures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+  Temp,
+  Fan,
+  Power,
+  Voltage,
+  Current,
+}
+
+impl Kind {
+  const ALL: [Kind; 5] = [Kind::Temp, Kind::Fan, Kind::Power, Kind::Voltage, Kind::Current];
+
+  /// The prefix of the files of this kind, like `temp` in `temp1_input`.
+  fn prefix(self) -> &'static str {
+    match self {
+      Kind::Temp => "temp",
+      Kind::Fan => "fan",
+      Kind::Power => "power",
+      Kind::Voltage => "in",
+      Kind::Current => "curr",
+    }
+  }
+
+  /// Returns what values in sysfs are divided by to get the unit of this kind.
+  /// Power is stored in microwatts, fans in RPM, and everything else in
+  /// thousandths.
+  fn scale(self) -> f32 {
+    match self {
+      Kind::Power => 1_000_000.0,
+      Kind::Fan => 1.0,
+      Kind::Temp | Kind::Voltage | Kind::Current => 1000.0,
+    }
+  }
+
+  /// The unit values are shown in, with the space that goes before it.
+  fn unit(self) -> &'static str {
+    match self {
+      Kind::Temp => "°",
+      Kind::Fan => " rpm",
+      Kind::Power => " W",
+      Kind::Voltage => " V",
+      Kind::Current => " A",
+    }
+  }
+
+  /// How many decimals to show.
+  fn precision(self) -> usize {
+    match self {
+      Kind::Temp | Kind::Fan => 0,
+      Kind::Power => 1,
+      Kind::Voltage | Kind::Current => 2,
+    }
+  }
 }
 
 impl Monitor {
-  pub fn new(path: &Path) -> Self {
-    let name = fs::read_to_string(path.join("name")).unwrap().trim().to_string();
-    let mut temps = vec![];
-    for entry in fs::read_dir(path).unwrap() {
-      let p = entry.unwrap();
-      // Every `temp` entry has two files: `temp1_input` and `temp1_label`, where `1`
-      // is the number of this sensor. So the easiest way to find these is to just
-      // look for the `temp1_label` files, then get the other files from that.
-
-      let path = p.path();
-      let name = path.file_name().unwrap().to_str().unwrap();
-      if name.starts_with("temp") && name.ends_with("label") {
-        let input_path = path.parent().unwrap().join(name.replace("label", "input"));
-        temps.push((
-          name.to_string(),
-          fs::read_to_string(path).unwrap().trim().to_string(),
-          File::open(input_path).unwrap(),
-        ));
+  pub fn new(path: &Path) -> io::Result<Self> {
+    let name = fs::read_to_string(path.join("name"))?.trim().to_string();
+    let path = fs::canonicalize(path)?;
+
+    let mut sensors = vec![];
+    for entry in fs::read_dir(&path)? {
+      let Ok(entry) = entry else { continue };
+      let file_name = entry.file_name();
+      let Some(SensorFile { stem, kind, index, suffix }) = file_name.to_str().and_then(parse_name)
+      else {
+        continue;
+      };
+
+      match suffix {
+        "input" => {}
+        // Some chips only have the average power draw.
+        "average" if kind == Kind::Power && !path.join(format!("{stem}_input")).exists() => {}
+        _ => continue,
       }
+
+      // Sensors that can't be opened are left out.
+      let Ok(file) = File::open(entry.path()) else { continue };
+      let label = match fs::read_to_string(path.join(format!("{stem}_label"))) {
+        Ok(label) => label.trim().to_string(),
+        Err(_) => stem.to_string(),
+      };
+
+      let threshold = |name: &str| {
+        let s = fs::read_to_string(path.join(format!("{stem}_{name}"))).ok()?;
+        parse(&s, kind.scale())
+      };
+      let (max, crit) = (threshold("max"), threshold("crit"));
+
+      sensors.push(((kind, index), Sensor { kind, label, path: entry.path(), file, max, crit }));
     }
-    temps.sort_unstable_by(|(a, _, _), (b, _, _)| a.cmp(b));
-    Monitor { name, temps: temps.into_iter().map(|(_, label, file)| (label, file)).collect() }
+    sensors.sort_unstable_by_key(|(key, _)| *key);
+
+    Ok(Monitor { name, path, sensors: sensors.into_iter().map(|(_, sensor)| sensor).collect() })
   }
 
   pub fn find_all() -> Vec<Monitor> {
-    fs::read_dir("/sys/class/hwmon")
-      .unwrap()
-      .map(|dir| Monitor::new(&dir.unwrap().path()))
-      .collect()
+    let Ok(dir) = fs::read_dir(crate::system_path("/sys/class/hwmon")) else { return vec![] };
+
+    // Chips that can't be read at all are skipped.
+    dir.filter_map(|entry| Monitor::new(&entry.ok()?.path()).ok()).collect()
   }
+}
 
-  pub fn read(&mut self) -> Vec<(String, f32)> {
-    self
-      .temps
-      .iter_mut()
-      .map(|(name, file)| {
-        file.seek(SeekFrom::Start(0)).unwrap();
+/// The parts of the name of a sensor file, like `fan1_input`.
+#[derive(Debug, PartialEq)]
+struct SensorFile<'a> {
+  /// The name of the sensor, like `fan1`, which all its files start with.
+  stem:   &'a str,
+  kind:   Kind,
+  index:  u32,
+  /// What the file holds, like `input` or `label`.
+  suffix: &'a str,
+}
 
-        let mut buf = vec![];
-        file.read_to_end(&mut buf).unwrap();
-        let s = String::from_utf8(buf).unwrap();
-        let milli_degress = s.trim().parse::<u32>().unwrap();
+/// Splits the name of a file in a hwmon directory. Every sensor has an input
+/// file, like `fan1_input`, where `fan` is the kind of sensor, and `1` is its
+/// number. Most also have a label file, like `fan1_label`.
+fn parse_name(name: &str) -> Option<SensorFile<'_>> {
+  let (stem, suffix) = name.rsplit_once('_')?;
+  Kind::ALL.into_iter().find_map(|kind| {
+    let index = stem.strip_prefix(kind.prefix())?.parse().ok()?;
+    Some(SensorFile { stem, kind, index, suffix })
+  })
+}
 
-        (name.to_string(), milli_degress as f32 / 1000.0)
-      })
-      .collect()
+impl Sensor {
+  /// Returns the current reading, in the unit of its kind, or `None` if it
+  /// can't be read right now.
+  pub fn read(&mut self) -> Option<f32> {
+    self.file.seek(SeekFrom::Start(0)).ok()?;
+
+    let mut s = String::new();
+    self.file.read_to_string(&mut s).ok()?;
+    parse(&s, self.kind.scale())
   }
 }
 
+/// Parses a value from sysfs, and divides it by `scale`.
+fn parse(s: &str, scale: f32) -> Option<f32> { Some(s.trim().parse::<i64>().ok()? as f32 / scale) }
+
 thread_local! {
   static MONITORS: RefCell<Option<Vec<Monitor>>> = RefCell::new(None);
 }
 
-#[derive(Clone)]
+/// Picks sensors out of `/sys/class/hwmon`. A sensor is picked if it matches
+/// every field that's set.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Selector {
+  /// The name of the chip, like `"k10temp"`, `"coretemp"` or `"amdgpu"`.
+  pub chip:  Option<String>,
+  /// The label of the sensor, like `"Tctl"`, `"Package id 0"` or `"edge"`.
+  pub label: Option<String>,
+  /// The hwmon directory, the directory of the device it belongs to, or the
+  /// input file of the sensor.
+  pub path:  Option<PathBuf>,
+}
+
+/// How the readings of several sensors are combined.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+  /// The highest reading.
+  #[default]
+  Max,
+  /// The average of all sensors.
+  Avg,
+  /// The total of all sensors, like the power draw of several devices.
+  Sum,
+}
+
+/// The CPU sensors of common chips, in order of preference. The first one that
+/// exists is shown when no sensors are configured.
+const CPU_SENSORS: &[(&str, Option<&str>)] = &[
+  ("k10temp", Some("Tctl")),
+  ("zenpower", Some("Tdie")),
+  ("coretemp", Some("Package id 0")),
+  ("cpu_thermal", None),
+  ("acpitz", None),
+];
+
+/// How many degrees below the first threshold the color starts to change.
+const RAMP: f32 = 10.0;
+
+/// How long one pulse takes above the critical threshold, in seconds.
+const PULSE_DURATION: f64 = 0.6;
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Temp {
-  pub primary:   Color,
-  pub secondary: Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub primary:        Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub secondary:      Color,
+  #[serde(default = "crate::default_interval", deserialize_with = "cb_bar::config::duration")]
+  pub interval:       Duration,
+  /// The sensors to show, combined with `aggregate`. If this is empty, the
+  /// sensor of the CPU is picked.
+  #[serde(default)]
+  pub sensors:        Vec<Selector>,
+  #[serde(default)]
+  pub aggregate:      Aggregate,
+  /// The temperature that is drawn in `warning_color`, in degrees Celsius.
+  /// This defaults to the lowest `max` threshold of the chosen sensors.
+  pub warning:        Option<f32>,
+  /// The temperature that is drawn in `critical_color`, and pulses above. This
+  /// defaults to the lowest `crit` threshold of the chosen sensors.
+  pub critical:       Option<f32>,
+  #[serde(default = "default_warning_color", deserialize_with = "cb_bar::config::color")]
+  pub warning_color:  Color,
+  #[serde(default = "default_critical_color", deserialize_with = "cb_bar::config::color")]
+  pub critical_color: Color,
 }
 struct TempModule {
   spec: Temp,
   text: Option<TextLayout>,
+
+  /// The chosen sensors, as indices into `MONITORS` and their `temps`. These
+  /// are found on the first layout.
+  sensors:   Option<Vec<(usize, usize)>>,
+  /// The thresholds from the config, or from the chosen sensors.
+  warning:   Option<f32>,
+  critical:  Option<f32>,
+  last_read: Instant,
+
+  /// Runs back and forth while the temperature is critical.
+  pulse: Animation,
 }
 
+fn default_warning_color() -> Color { cb_core::oklch(0.75, 0.17, 70.0) }
+fn default_critical_color() -> Color { cb_core::oklch(0.65, 0.22, 25.0) }
+
 impl From<Temp> for Box<dyn Module> {
-  fn from(spec: Temp) -> Self { Box::new(TempModule { spec, text: None }) }
+  fn from(spec: Temp) -> Self {
+    Box::new(TempModule {
+      spec,
+      text: None,
+      sensors: None,
+      warning: None,
+      critical: None,
+      last_read: Instant::now(),
+      pulse: Animation::ease_in_out(PULSE_DURATION),
+    })
+  }
+}
+
+impl Selector {
+  fn matches(&self, monitor: &Monitor, sensor: &Sensor) -> bool {
+    if self.chip.as_ref().is_some_and(|chip| *chip != monitor.name) {
+      return false;
+    }
+    if self.label.as_ref().is_some_and(|label| *label != sensor.label) {
+      return false;
+    }
+    if let Some(path) = &self.path {
+      let Ok(path) = fs::canonicalize(path) else { return false };
+      if !monitor.path.starts_with(&path) && sensor.path != path {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+impl Aggregate {
+  fn apply(self, values: impl Iterator<Item = f32>) -> Option<f32> {
+    match self {
+      Aggregate::Max => values.reduce(f32::max),
+      Aggregate::Avg => {
+        let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+        (count > 0).then(|| sum / count as f32)
+      }
+      Aggregate::Sum => values.reduce(|a, b| a + b),
+    }
+  }
+}
+
+/// Returns the sensors of `kind` in `monitors` that match any of `selectors`.
+fn select(monitors: &[Monitor], kind: Kind, selectors: &[Selector]) -> Vec<(usize, usize)> {
+  let mut found = vec![];
+  for (i, monitor) in monitors.iter().enumerate() {
+    for (j, sensor) in monitor.sensors.iter().enumerate() {
+      if sensor.kind == kind && selectors.iter().any(|s| s.matches(monitor, sensor)) {
+        found.push((i, j));
+      }
+    }
+  }
+  found
+}
+
+/// Finds the sensors of `kind` to show. Without any `selectors`, this picks
+/// the first of `defaults` that exists, as a chip name and label, or every
+/// sensor of `kind` if there are no `defaults`.
+fn find_sensors(
+  monitors: &[Monitor],
+  kind: Kind,
+  selectors: &[Selector],
+  defaults: &[(&str, Option<&str>)],
+) -> Vec<(usize, usize)> {
+  if !selectors.is_empty() {
+    return select(monitors, kind, selectors);
+  }
+  if defaults.is_empty() {
+    return select(monitors, kind, &[Selector::default()]);
+  }
+
+  defaults
+    .iter()
+    .map(|&(chip, label)| Selector {
+      chip: Some(chip.into()),
+      label: label.map(Into::into),
+      path: None,
+    })
+    .map(|selector| select(monitors, kind, &[selector]))
+    .find(|found| !found.is_empty())
+    .unwrap_or_default()
+}
+
+/// Reads `sensors`, and combines them. Returns `None` if none of them could be
+/// read.
+fn read(monitors: &mut [Monitor], sensors: &[(usize, usize)], aggregate: Aggregate) -> Option<f32> {
+  let values = sensors.iter().filter_map(|&(i, j)| monitors[i].sensors[j].read());
+  aggregate.apply(values)
+}
+
+/// Lists every sensor of `kind`, not just the ones shown in the bar.
+fn tooltip(kind: Kind, primary: Color, secondary: Color) -> Option<Text<'static>> {
+  MONITORS.with(|s| {
+    let mut monitors = s.borrow_mut();
+    let monitors = monitors.as_mut()?;
+
+    let mut text = Text::new();
+    for mon in monitors.iter_mut() {
+      for sensor in mon.sensors.iter_mut().filter(|s| s.kind == kind) {
+        let Some(value) = sensor.read() else { continue };
+        if !text.is_empty() {
+          text.push("\n", secondary);
+        }
+        text.push(format_args!("{} {}: ", mon.name, sensor.label), secondary);
+        text.push(format_args!("{value:.0$}", kind.precision()), primary);
+        text.push(kind.unit(), secondary);
+      }
+    }
+    Some(text)
+  })
+}
+
+impl TempModule {
+  /// Reads the chosen sensors, and combines them. Returns `None` if none of
+  /// them could be read.
+  fn read(&mut self, monitors: &mut [Monitor]) -> Option<f32> {
+    let sensors = self.sensors.get_or_insert_with(|| {
+      let sensors = find_sensors(monitors, Kind::Temp, &self.spec.sensors, CPU_SENSORS);
+      let lowest = |threshold: fn(&Sensor) -> Option<f32>| {
+        sensors.iter().filter_map(|&(i, j)| threshold(&monitors[i].sensors[j])).reduce(f32::min)
+      };
+      self.warning = self.spec.warning.or_else(|| lowest(|s| s.max));
+      self.critical = self.spec.critical.or_else(|| lowest(|s| s.crit));
+      sensors
+    });
+
+    self.last_read = Instant::now();
+    read(monitors, sensors, self.spec.aggregate)
+  }
+
+  /// Returns the color to draw `temp` in. This fades from `primary` to the
+  /// warning color, and then on to the critical color, as `temp` gets closer
+  /// to each threshold.
+  fn color(&self, temp: f32) -> Color {
+    let stops = [(self.warning, self.spec.warning_color), (self.critical, self.spec.critical_color)];
+
+    let mut prev = None;
+    for (threshold, color) in stops {
+      let Some(threshold) = threshold else { continue };
+      let (start, start_color) = prev.unwrap_or((threshold - RAMP, self.spec.primary));
+
+      if temp < threshold {
+        let t = if threshold > start { ((temp - start) / (threshold - start)).max(0.0) } else { 1.0 };
+        return lerp_oklch(start_color, color, t);
+      }
+      prev = Some((threshold, color));
+    }
+
+    prev.map_or(self.spec.primary, |(_, color)| color)
+  }
+}
+
+/// Blends `a` into `b` in OkLCH, so that the hue changes, instead of going
+/// through gray.
+fn lerp_oklch(a: Color, b: Color, t: f32) -> Color {
+  a.convert::<Oklch>().lerp(b.convert::<Oklch>(), t, HueDirection::Shorter).convert::<Oklab>()
 }
 
 impl Module for TempModule {
-  fn updater(&self) -> Updater<'_> { Updater::Every(Duration::from_secs(1)) }
+  fn updater(&self) -> Updater<'_> {
+    // Animations don't lay out the module again, so the sensors still need to
+    // be read on time while pulsing.
+    let next_read = self.last_read + self.spec.interval;
+    if self.pulse.is_running() && Instant::now() < next_read {
+      Updater::Animation
+    } else {
+      Updater::At(next_read)
+    }
+  }
+  fn tooltip(&self) -> Option<Text<'static>> {
+    tooltip(Kind::Temp, self.spec.primary, self.spec.secondary)
+  }
   fn layout(&mut self, layout: &mut Layout) {
     layout.pad(5.0);
 
     MONITORS.with(|s| {
       let mut monitors = s.borrow_mut();
-      if monitors.is_none() {
-        *monitors = Some(Monitor::find_all());
+      let monitors = monitors.get_or_insert_with(Monitor::find_all);
+
+      let temp = self.read(monitors);
+
+      let mut text = Text::new();
+      match temp {
+        Some(temp) => text.push(format_args!("{temp:>2.0}"), self.color(temp)),
+        // Keep the module in place, so it's clear the sensor is missing.
+        None => text.push("--", self.spec.secondary),
       }
-      let m = monitors.as_mut().unwrap();
+      text.push("°", self.spec.secondary);
 
-      if let Some(mon) = m.iter_mut().find(|m| m.name == "k10temp") {
-        let results = mon.read();
+      self.text = Some(layout.layout_text(text, self.spec.primary));
 
-        let mut text = Text::new();
-        text.push(&format!("{:>2.00}", results[0].1), self.spec.primary);
-        text.push("°", self.spec.secondary);
+      let critical = temp.zip(self.critical).is_some_and(|(temp, critical)| temp >= critical);
+      if critical {
+        self.pulse.repeat();
+      } else if self.pulse.is_repeating() {
+        self.pulse.stop();
+      }
+    });
 
-        self.text = Some(layout.layout_text(text, self.spec.primary));
+    layout.pad(5.0);
+  }
+
+  fn render(&self, render: &mut Render) {
+    self.pulse.advance(render.frame_time());
+
+    if let Some(text) = &self.text {
+      let glow = self.pulse.interpolate(0.0, 0.4) as f32;
+      if glow > 0.0 {
+        let rect = RoundedRect::from_rect(text.bounds().inflate(3.0, 1.0), 3.0);
+        render.fill(&rect, self.spec.critical_color.multiply_alpha(glow));
       }
+
+      render.draw(text);
+    }
+  }
+}
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fan {
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub secondary: Color,
+  #[serde(default = "crate::default_interval", deserialize_with = "cb_bar::config::duration")]
+  pub interval:  Duration,
+  /// The fans to show, combined with `aggregate`. If this is empty, every fan
+  /// is picked.
+  #[serde(default)]
+  pub sensors:   Vec<Selector>,
+  #[serde(default)]
+  pub aggregate: Aggregate,
+}
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Power {
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
+  pub secondary: Color,
+  #[serde(default = "crate::default_interval", deserialize_with = "cb_bar::config::duration")]
+  pub interval:  Duration,
+  /// The sensors to show, combined with `aggregate`. If this is empty, the
+  /// power drawn by the CPU packages is shown, from their RAPL energy
+  /// counters in `/sys/class/powercap`. Most systems only let root read
+  /// those, and `--` is shown then.
+  #[serde(default)]
+  pub sensors:   Vec<Selector>,
+  #[serde(default)]
+  pub aggregate: Aggregate,
+}
+
+/// The energy counters of the CPU packages, which give the power they drew
+/// between two readings. Intel and AMD CPUs both report these as RAPL zones.
+struct Packages {
+  zones: Vec<Zone>,
+  /// The time of the last reading, and the counter of every zone then.
+  last:  Option<(Instant, Vec<u64>)>,
+}
+
+struct Zone {
+  /// The `energy_uj` file.
+  file:  File,
+  /// The value the counter wraps around at, from `max_energy_range_uj`.
+  range: u64,
+}
+
+impl Packages {
+  /// Finds the package zones, like `intel-rapl:0`, while leaving out the
+  /// zones inside of them, like `intel-rapl:0:0` for the cores.
+  fn find() -> Packages {
+    let dir = crate::system_path("/sys/class/powercap");
+    let mut names = fs::read_dir(&dir)
+      .into_iter()
+      .flatten()
+      .flatten()
+      .filter_map(|entry| entry.file_name().into_string().ok())
+      .filter(|name| name.matches(':').count() == 1)
+      .collect::<Vec<_>>();
+    names.sort_unstable();
+
+    let zones = names
+      .iter()
+      .map(|name| dir.join(name))
+      .filter(|path| {
+        fs::read_to_string(path.join("name")).is_ok_and(|name| name.starts_with("package"))
+      })
+      .filter_map(|path| {
+        let range = fs::read_to_string(path.join("max_energy_range_uj")).ok()?;
+        let file = File::open(path.join("energy_uj"))
+          .inspect_err(|e| eprintln!("power: cannot read {}: {e}", path.display()))
+          .ok()?;
+        Some(Zone { file, range: range.trim().parse().ok()? })
+      })
+      .collect();
+
+    Packages { zones, last: None }
+  }
+
+  /// Returns the power drawn since the last call, in watts. Returns `None` on
+  /// the first call, or if any of the counters can't be read.
+  fn read(&mut self) -> Option<f32> {
+    let now = Instant::now();
+    let counters = self
+      .zones
+      .iter_mut()
+      .map(|zone| {
+        zone.file.seek(SeekFrom::Start(0)).ok()?;
+        let mut s = String::new();
+        zone.file.read_to_string(&mut s).ok()?;
+        s.trim().parse::<u64>().ok()
+      })
+      .collect::<Option<Vec<_>>>()
+      .filter(|counters| !counters.is_empty())?;
+
+    let (time, last) = self.last.replace((now, counters.clone()))?;
+    let seconds = now.duration_since(time).as_secs_f64();
+    if seconds <= 0.0 {
+      return None;
+    }
+
+    let energy = self
+      .zones
+      .iter()
+      .zip(last.iter().zip(&counters))
+      .map(|(zone, (&last, &now))| energy_since(last, now, zone.range))
+      .sum::<u64>();
+    Some((energy as f64 / 1_000_000.0 / seconds) as f32)
+  }
+}
+
+/// Returns how much energy was used between the readings `last` and `now` of
+/// a counter that wraps around at `range`.
+fn energy_since(last: u64, now: u64, range: u64) -> u64 {
+  if now >= last { now - last } else { range.saturating_sub(last) + now }
+}
+
+/// Shows a single reading, like the speed of a fan.
+struct ReadingModule {
+  kind:      Kind,
+  primary:   Color,
+  secondary: Color,
+  interval:  Duration,
+  selectors: Vec<Selector>,
+  aggregate: Aggregate,
+
+  text:     Option<TextLayout>,
+  /// The chosen sensors, which are found on the first layout.
+  sensors:  Option<Vec<(usize, usize)>>,
+  /// The CPU packages, which are read instead of `sensors` for the power
+  /// module, if no sensors are configured.
+  packages: Option<Packages>,
+}
+
+impl From<Fan> for Box<dyn Module> {
+  fn from(spec: Fan) -> Self {
+    Box::new(ReadingModule {
+      kind:      Kind::Fan,
+      primary:   spec.primary,
+      secondary: spec.secondary,
+      interval:  spec.interval,
+      selectors: spec.sensors,
+      aggregate: spec.aggregate,
+      text:      None,
+      sensors:   None,
+      packages:  None,
+    })
+  }
+}
+
+impl From<Power> for Box<dyn Module> {
+  fn from(spec: Power) -> Self {
+    let packages = spec.sensors.is_empty().then(Packages::find);
+    Box::new(ReadingModule {
+      kind: Kind::Power,
+      primary: spec.primary,
+      secondary: spec.secondary,
+      interval: spec.interval,
+      selectors: spec.sensors,
+      aggregate: spec.aggregate,
+      text: None,
+      sensors: None,
+      packages,
+    })
+  }
+}
+
+impl Module for ReadingModule {
+  fn updater(&self) -> Updater<'_> { Updater::Every(self.interval) }
+  fn tooltip(&self) -> Option<Text<'static>> { tooltip(self.kind, self.primary, self.secondary) }
+  fn layout(&mut self, layout: &mut Layout) {
+    layout.pad(5.0);
+
+    MONITORS.with(|s| {
+      let mut monitors = s.borrow_mut();
+      let monitors = monitors.get_or_insert_with(Monitor::find_all);
+
+      let value = match &mut self.packages {
+        Some(packages) => packages.read(),
+        None => {
+          let sensors = self
+            .sensors
+            .get_or_insert_with(|| find_sensors(monitors, self.kind, &self.selectors, &[]));
+          read(monitors, sensors, self.aggregate)
+        }
+      };
+
+      let mut text = Text::new();
+      match value {
+        Some(value) => text.push(format_args!("{value:.0$}", self.kind.precision()), self.primary),
+        // Keep the module in place, so it's clear the sensor is missing.
+        None => text.push("--", self.secondary),
+      }
+      text.push(self.kind.unit(), self.secondary);
+
+      self.text = Some(layout.layout_text(text, self.primary));
     });
 
     layout.pad(5.0);
@@ -121,3 +707,211 @@ impl Module for TempModule {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The hwmon directory `name` of the test files.
+  fn hwmon(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/root/sys/class/hwmon").join(name)
+  }
+
+  fn monitors() -> Vec<Monitor> {
+    vec![Monitor::new(&hwmon("hwmon0")).unwrap(), Monitor::new(&hwmon("hwmon1")).unwrap()]
+  }
+
+  fn selector(chip: Option<&str>, label: Option<&str>, path: Option<PathBuf>) -> Selector {
+    Selector { chip: chip.map(Into::into), label: label.map(Into::into), path }
+  }
+
+  #[test]
+  fn parses_file_names() {
+    let file = |stem, kind, index, suffix| Some(SensorFile { stem, kind, index, suffix });
+
+    assert_eq!(parse_name("temp1_input"), file("temp1", Kind::Temp, 1, "input"));
+    assert_eq!(parse_name("fan12_label"), file("fan12", Kind::Fan, 12, "label"));
+    assert_eq!(parse_name("power1_average"), file("power1", Kind::Power, 1, "average"));
+    assert_eq!(parse_name("in0_input"), file("in0", Kind::Voltage, 0, "input"));
+    assert_eq!(parse_name("curr2_crit"), file("curr2", Kind::Current, 2, "crit"));
+
+    // Alarms have a second suffix, which leaves the number unparsable.
+    assert_eq!(parse_name("temp1_crit_alarm"), None);
+    // `intrusion` starts like `in`, for voltages.
+    assert_eq!(parse_name("intrusion0_alarm"), None);
+    assert_eq!(parse_name("name"), None);
+    assert_eq!(parse_name("temp_input"), None);
+    assert_eq!(parse_name("pwm1_enable"), None);
+  }
+
+  #[test]
+  fn reads_sensor_files() {
+    let monitor = Monitor::new(&hwmon("hwmon1")).unwrap();
+    let sensors =
+      monitor.sensors.iter().map(|s| (s.kind, s.label.as_str(), s.max, s.crit)).collect::<Vec<_>>();
+    assert_eq!(sensors, [
+      (Kind::Temp, "temp1", Some(80.0), Some(95.0)),
+      (Kind::Fan, "fan1", None, None),
+      (Kind::Fan, "CPU Fan", None, None),
+    ]);
+
+    // Only the average is read if there's no input.
+    let mut monitor = Monitor::new(&hwmon("hwmon2")).unwrap();
+    let power = monitor.sensors.iter_mut().find(|s| s.kind == Kind::Power).unwrap();
+    assert_eq!(power.label, "PPT");
+    assert_eq!(power.read(), Some(45.3));
+  }
+
+  #[test]
+  fn measures_energy_across_wrapping() {
+    assert_eq!(energy_since(100, 250, 1000), 150);
+    assert_eq!(energy_since(900, 50, 1000), 150);
+    assert_eq!(energy_since(100, 100, 1000), 0);
+  }
+
+  #[test]
+  fn reads_package_power() {
+    crate::set_system_root(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/root"));
+
+    // Only `intel-rapl:0` is a package, the others are its cores, and the
+    // whole platform.
+    let mut packages = Packages::find();
+    assert_eq!(packages.zones.len(), 1);
+    assert_eq!(packages.zones[0].range, 262143328850);
+
+    // The first reading has nothing to compare against, and the test files
+    // never change.
+    assert_eq!(packages.read(), None);
+    std::thread::sleep(Duration::from_millis(1));
+    assert_eq!(packages.read(), Some(0.0));
+  }
+
+  #[test]
+  fn aggregates() {
+    let values = || [3.0, 1.0, 5.0].into_iter();
+    assert_eq!(Aggregate::Max.apply(values()), Some(5.0));
+    assert_eq!(Aggregate::Avg.apply(values()), Some(3.0));
+    assert_eq!(Aggregate::Sum.apply(values()), Some(9.0));
+
+    for aggregate in [Aggregate::Max, Aggregate::Avg, Aggregate::Sum] {
+      assert_eq!(aggregate.apply(std::iter::empty()), None);
+    }
+  }
+
+  #[test]
+  fn selects_sensors() {
+    let monitors = monitors();
+    let select = |selector| select(&monitors, Kind::Temp, &[selector]);
+
+    // k10temp has Tctl and Tccd1, and nct6798 has temp1.
+    assert_eq!(select(selector(None, None, None)), [(0, 0), (0, 1), (1, 0)]);
+    assert_eq!(select(selector(Some("k10temp"), None, None)), [(0, 0), (0, 1)]);
+    assert_eq!(select(selector(None, Some("Tccd1"), None)), [(0, 1)]);
+    assert_eq!(select(selector(Some("nct6798"), Some("Tccd1"), None)), []);
+    assert_eq!(select(selector(Some("coretemp"), None, None)), []);
+  }
+
+  #[test]
+  fn selects_sensors_by_path() {
+    let mut monitors = monitors();
+    // Like in sysfs, this is a link into the directory of its device.
+    monitors.push(Monitor::new(&hwmon("hwmon3")).unwrap());
+    let select = |path| select(&monitors, Kind::Temp, &[selector(None, None, Some(path))]);
+
+    assert_eq!(select(hwmon("hwmon1")), [(1, 0)]);
+    assert_eq!(select(hwmon("hwmon0").join("temp3_input")), [(0, 1)]);
+    assert_eq!(select(hwmon("hwmon3")), [(2, 0)]);
+    assert_eq!(select(hwmon("hwmon9")), []);
+
+    // The device, which holds the hwmon directory, by its path and through
+    // the link to it.
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/root");
+    assert_eq!(select(root.join("sys/devices/pci0000:00/0000:00:01.1/0000:01:00.0")), [(2, 0)]);
+    assert_eq!(select(hwmon("hwmon3").join("device")), [(2, 0)]);
+    assert_eq!(select(root.join("sys/devices/pci0000:00/0000:00:01.1")), [(2, 0)]);
+  }
+
+  #[test]
+  fn finds_default_sensors() {
+    let monitors = monitors();
+
+    let found = find_sensors(&monitors, Kind::Temp, &[], CPU_SENSORS);
+    assert_eq!(found, [(0, 0)]);
+
+    // Selectors replace the defaults, and sensors match any of them.
+    let selectors = [selector(Some("nct6798"), None, None), selector(None, Some("Tccd1"), None)];
+    let found = find_sensors(&monitors, Kind::Temp, &selectors, CPU_SENSORS);
+    assert_eq!(found, [(0, 1), (1, 0)]);
+
+    assert_eq!(find_sensors(&monitors, Kind::Fan, &[], &[]), [(1, 1), (1, 2)]);
+  }
+
+  fn temp_module(warning: Option<f32>, critical: Option<f32>) -> TempModule {
+    let spec = Temp {
+      primary: cb_core::oklch(0.9, 0.0, 0.0),
+      secondary: cb_core::oklch(0.6, 0.0, 0.0),
+      interval: Duration::from_secs(1),
+      sensors: vec![],
+      aggregate: Aggregate::Max,
+      warning,
+      critical,
+      warning_color: default_warning_color(),
+      critical_color: default_critical_color(),
+    };
+    TempModule {
+      spec,
+      text: None,
+      sensors: None,
+      warning,
+      critical,
+      last_read: Instant::now(),
+      pulse: Animation::ease_in_out(PULSE_DURATION),
+    }
+  }
+
+  #[track_caller]
+  fn assert_color(actual: Color, expected: Color) {
+    let close = actual.components.iter().zip(expected.components).all(|(a, b)| (a - b).abs() < 1e-4);
+    assert!(close, "{actual:?} != {expected:?}");
+  }
+
+  #[test]
+  fn ramps_through_thresholds() {
+    let module = temp_module(Some(70.0), Some(90.0));
+    let (primary, warning, critical) =
+      (module.spec.primary, module.spec.warning_color, module.spec.critical_color);
+
+    assert_color(module.color(40.0), primary);
+    assert_color(module.color(60.0), primary);
+    assert_color(module.color(65.0), lerp_oklch(primary, warning, 0.5));
+    assert_color(module.color(70.0), warning);
+    assert_color(module.color(80.0), lerp_oklch(warning, critical, 0.5));
+    assert_color(module.color(90.0), critical);
+    assert_color(module.color(110.0), critical);
+  }
+
+  #[test]
+  fn ramps_with_one_threshold() {
+    let module = temp_module(None, Some(90.0));
+    let (primary, critical) = (module.spec.primary, module.spec.critical_color);
+
+    assert_color(module.color(70.0), primary);
+    assert_color(module.color(85.0), lerp_oklch(primary, critical, 0.5));
+    assert_color(module.color(95.0), critical);
+
+    let module = temp_module(None, None);
+    assert_color(module.color(150.0), module.spec.primary);
+  }
+
+  #[test]
+  fn ramps_with_thresholds_out_of_order() {
+    // The critical color is only reached past both thresholds.
+    let module = temp_module(Some(90.0), Some(80.0));
+    let (primary, warning) = (module.spec.primary, module.spec.warning_color);
+    assert_color(module.color(85.0), lerp_oklch(primary, warning, 0.5));
+    assert_color(module.color(95.0), module.spec.critical_color);
+
+    let module = temp_module(Some(80.0), Some(80.0));
+    assert_color(module.color(80.0), module.spec.critical_color);
+  }
+}
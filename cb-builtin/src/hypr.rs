@@ -1,26 +1,31 @@
 use parking_lot::Mutex;
 use std::{
   cell::{Cell, RefCell},
-  io::{BufRead, BufReader, Read, Write},
+  io::{self, Read, Write},
   os::unix::net::UnixStream,
   path::PathBuf,
-  sync::Arc,
 };
 
-use cb_bar::{Animation, Module, TextLayout};
-use cb_core::{Color, Waker};
-use kurbo::Point;
+use cb_bar::{Animation, Axis, Module, TextLayout};
+use cb_core::{Click, Color, EventLoop, MouseButton, PostAction, Scroll};
+use kurbo::{Point, Rect};
 
 use crate::{Dirty, UpdateGroup};
 
-#[derive(Clone)]
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Hypr {
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub secondary: Color,
 }
 
 struct HyprModule {
   spec:         Hypr,
+  axis:         Axis,
+  /// Scroll distance that hasn't added up to a whole workspace yet.
+  scroll:       f64,
   workspaces:   Vec<WorkspaceLayout>,
   dirty:        Dirty,
   render_dirty: Cell<bool>,
@@ -41,6 +46,8 @@ impl From<Hypr> for Box<dyn Module> {
   fn from(spec: Hypr) -> Self {
     Box::new(HyprModule {
       spec,
+      axis: Axis::Horizontal,
+      scroll: 0.0,
       workspaces: vec![],
       dirty: UPDATERS.lock().add(),
       render_dirty: Cell::new(false),
@@ -154,80 +161,91 @@ struct Workspace {
   focused: bool,
 }
 
-fn spawn_listener(waker: &Arc<Waker>) {
-  use std::sync::atomic::*;
-
-  static RUNNING: AtomicBool = AtomicBool::new(false);
+/// Starts listening to the Hyprland event socket from the main loop, if that
+/// isn't happening already.
+fn listen(events: &EventLoop) {
+  thread_local! {
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+  }
 
-  if !RUNNING.swap(true, Ordering::SeqCst) {
-    STATE.lock().setup();
-    let waker = waker.clone();
-    std::thread::spawn(move || listen(waker));
+  if LISTENING.replace(true) {
+    return;
   }
-}
 
-fn listen(waker: Arc<Waker>) {
+  STATE.lock().setup();
+
   let sig = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").unwrap();
   let runtime = std::env::var("XDG_RUNTIME_DIR").unwrap();
 
-  let mut reader =
-    BufReader::new(UnixStream::connect(format!("{runtime}/hypr/{sig}/.socket2.sock")).unwrap());
-
-  let mut line = String::new();
-  loop {
-    line.clear();
-    match reader.read_line(&mut line) {
-      Ok(0) => {
-        eprintln!("hypr: connection closed");
-        break;
-      }
-      Ok(_) => {}
-      Err(e) => {
-        eprintln!("hypr: {e}");
-        break;
+  let stream = UnixStream::connect(format!("{runtime}/hypr/{sig}/.socket2.sock")).unwrap();
+  stream.set_nonblocking(true).unwrap();
+
+  // Events can be split across reads, so keep partial lines around.
+  let mut buf = vec![];
+  events.add_fd(stream, move |stream| {
+    let mut chunk = [0; 4096];
+    loop {
+      match stream.read(&mut chunk) {
+        Ok(0) => {
+          eprintln!("hypr: connection closed");
+          return PostAction::Remove;
+        }
+        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => {
+          eprintln!("hypr: {e}");
+          return PostAction::Remove;
+        }
       }
     }
 
-    let Some((ev, args)) = line.split_once(">>") else { continue };
+    while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+      let line = buf.drain(..=end).collect::<Vec<u8>>();
+      handle_event(&String::from_utf8_lossy(&line));
+    }
 
-    let ev = ev.trim();
-    let args = args.trim();
+    PostAction::Continue
+  });
+}
 
-    match ev {
-      "workspacev2" => {
-        let Some((workspace, _name)) = args.split_once(',') else { continue };
-        let Ok(workspace) = workspace.parse::<u32>() else { continue };
-        {
-          let mut state = STATE.lock();
-          state.focus_workspace(workspace);
-        }
-        UPDATERS.lock().mark_dirty();
-        waker.wake();
+fn handle_event(line: &str) {
+  let Some((ev, args)) = line.split_once(">>") else { return };
+
+  let ev = ev.trim();
+  let args = args.trim();
+
+  match ev {
+    "workspacev2" => {
+      let Some((workspace, _name)) = args.split_once(',') else { return };
+      let Ok(workspace) = workspace.parse::<u32>() else { return };
+      {
+        let mut state = STATE.lock();
+        state.focus_workspace(workspace);
       }
-      "destroyworkspacev2" => {
-        let Some((workspace, _name)) = args.split_once(',') else { continue };
-        let Ok(workspace) = workspace.parse::<u32>() else { continue };
-        {
-          let mut state = STATE.lock();
-          state.destroy_workspace(workspace);
-        }
-        UPDATERS.lock().mark_dirty();
-        waker.wake();
+      UPDATERS.lock().mark_dirty();
+    }
+    "destroyworkspacev2" => {
+      let Some((workspace, _name)) = args.split_once(',') else { return };
+      let Ok(workspace) = workspace.parse::<u32>() else { return };
+      {
+        let mut state = STATE.lock();
+        state.destroy_workspace(workspace);
       }
-      "focusedmonv2" => {
-        let Some((mon, workspace)) = args.split_once(',') else { continue };
-        let Ok(workspace) = workspace.parse::<u32>() else { continue };
-        {
-          let mut state = STATE.lock();
-          state.focus_monitor(mon);
-          state.focus_workspace(workspace);
-        }
-        UPDATERS.lock().mark_dirty();
-        waker.wake();
+      UPDATERS.lock().mark_dirty();
+    }
+    "focusedmonv2" => {
+      let Some((mon, workspace)) = args.split_once(',') else { return };
+      let Ok(workspace) = workspace.parse::<u32>() else { return };
+      {
+        let mut state = STATE.lock();
+        state.focus_monitor(mon);
+        state.focus_workspace(workspace);
       }
-
-      _ => {}
+      UPDATERS.lock().mark_dirty();
     }
+
+    _ => {}
   }
 }
 
@@ -300,6 +318,17 @@ impl HyprState {
   }
 }
 
+impl HyprModule {
+  /// The bounds of the button behind a workspace, which extends past the text
+  /// along the bar.
+  fn button(&self, workspace: &WorkspaceLayout) -> Rect {
+    match self.axis {
+      Axis::Horizontal => workspace.text.bounds().inflate(5.0, 0.0),
+      Axis::Vertical => workspace.text.bounds().inflate(0.0, 5.0),
+    }
+  }
+}
+
 impl Module for HyprModule {
   fn updater(&self) -> cb_bar::Updater<'_> {
     if self.render_dirty.get() || self.workspaces.iter().any(|w| w.focus_animation.is_running()) {
@@ -312,8 +341,9 @@ impl Module for HyprModule {
   fn on_mouse(&mut self, _: Point) { self.render_dirty.set(true); }
 
   fn layout(&mut self, layout: &mut cb_bar::Layout) {
-    spawn_listener(layout.waker);
+    listen(layout.events);
     self.dirty.clear();
+    self.axis = layout.axis();
 
     layout.pad(10.0);
 
@@ -349,15 +379,31 @@ impl Module for HyprModule {
     layout.pad(10.0);
   }
 
-  fn on_click(&mut self, cursor: Point) {
+  fn on_click(&mut self, cursor: Point, click: Click) {
     for workspace in &self.workspaces {
-      if workspace.text.bounds().inflate(5.0, 0.0).contains(cursor) {
-        Connection::from_env().dispatch(&format!("workspace {}", workspace.id));
-        STATE.lock().focus_workspace(workspace.id);
+      if self.button(workspace).contains(cursor) {
+        if click.is_press(MouseButton::Left) {
+          Connection::from_env().dispatch(&format!("workspace {}", workspace.id));
+          STATE.lock().focus_workspace(workspace.id);
+        } else if click.is_press(MouseButton::Right) {
+          // Send the focused window to this workspace, without following it.
+          Connection::from_env().dispatch(&format!("movetoworkspacesilent {}", workspace.id));
+        }
       }
     }
   }
 
+  fn on_scroll(&mut self, _: Point, scroll: Scroll) {
+    // Scroll through the open workspaces on the focused monitor.
+    self.scroll += scroll.steps_y() + scroll.steps_x();
+
+    let steps = self.scroll.trunc();
+    if steps != 0.0 {
+      self.scroll -= steps;
+      Connection::from_env().dispatch(&format!("workspace e{:+}", steps as i32));
+    }
+  }
+
   fn render(&self, ctx: &mut cb_core::Render) {
     self.render_dirty.set(false);
 
@@ -382,7 +428,7 @@ impl Module for HyprModule {
         target_color
       };
 
-      ctx.draw_button(&workspace.text.bounds().inflate(5.0, 0.0), color);
+      ctx.draw_button(&self.button(workspace), color);
       ctx.draw_text_layout(workspace.text.origin, &workspace.text.layout, Some(color.into()));
     }
   }
@@ -1,8 +1,13 @@
-use std::sync::{
-  Arc, Weak,
-  atomic::{AtomicBool, Ordering},
+use std::{
+  sync::{
+    Arc, Weak,
+    atomic::{AtomicBool, Ordering},
+  },
+  time::Duration,
 };
 
+use cb_bar::Registry;
+
 macro_rules! feature_mod {
   ($mod:ident, $feature:literal) => {
     #[cfg(feature = $feature)]
@@ -13,12 +18,109 @@ macro_rules! feature_mod {
   };
 }
 
+feature_mod!(backlight, "backlight");
+feature_mod!(battery, "battery");
 feature_mod!(clock, "clock");
 feature_mod!(hwmon, "hwmon");
 feature_mod!(hypr, "hypr");
 feature_mod!(proc, "proc");
 feature_mod!(pulse, "pulse");
 
+/// Returns a registry of every builtin module, for loading bars from a config
+/// file.
+pub fn registry() -> Registry {
+  #[allow(unused_mut)]
+  let mut registry = Registry::new();
+
+  #[cfg(feature = "backlight")]
+  registry.register::<Backlight>("backlight");
+  #[cfg(feature = "battery")]
+  registry.register::<Battery>("battery");
+  #[cfg(feature = "clock")]
+  registry.register::<Clock>("clock");
+  #[cfg(feature = "hwmon")]
+  {
+    registry.register::<Temp>("temp");
+    registry.register::<Fan>("fan");
+    registry.register::<Power>("power");
+  }
+  #[cfg(feature = "hypr")]
+  registry.register::<Hypr>("hypr");
+  #[cfg(feature = "proc")]
+  {
+    registry.register::<Cpu>("cpu");
+    registry.register::<Mem>("mem");
+  }
+  #[cfg(feature = "pulse")]
+  registry.register::<Pulse>("pulse");
+
+  registry
+}
+
+/// Where system files are read from, if not `/`.
+#[cfg(any(test, feature = "snapshot"))]
+static SYSTEM_ROOT: parking_lot::RwLock<Option<std::path::PathBuf>> =
+  parking_lot::RwLock::new(None);
+
+/// Reads system files, like the ones in `/sys` and `/proc`, from `root`
+/// instead, to test modules against fixed files.
+#[cfg(any(test, feature = "snapshot"))]
+pub fn set_system_root(root: impl Into<std::path::PathBuf>) {
+  *SYSTEM_ROOT.write() = Some(root.into());
+}
+
+/// Returns where the system file at `path`, like `/proc/stat`, is read from.
+/// Outside of tests, that's always `path` itself.
+#[cfg(any(feature = "backlight", feature = "battery", feature = "hwmon", feature = "proc"))]
+fn system_path(path: &str) -> std::path::PathBuf {
+  #[cfg(any(test, feature = "snapshot"))]
+  if let Some(root) = &*SYSTEM_ROOT.read() {
+    return root.join(path.trim_start_matches('/'));
+  }
+
+  std::path::PathBuf::from(path)
+}
+
+#[allow(dead_code)]
+fn default_interval() -> Duration { Duration::from_secs(1) }
+
+/// Draws a line next to `text` that fills up to `fract`, from the bottom, or
+/// from the left in a vertical bar, where it sits above the text. Modules that
+/// use this leave 10 pixels of padding before the text.
+#[allow(dead_code)]
+fn draw_level(
+  ctx: &mut cb_core::Render,
+  text: &cb_bar::TextLayout,
+  axis: cb_bar::Axis,
+  fract: f64,
+  background: cb_core::Color,
+  level: cb_core::Color,
+) {
+  use kurbo::Line;
+
+  let (background_line, level_line) = match axis {
+    cb_bar::Axis::Horizontal => {
+      let min_y = text.bounds().y0 - 2.0;
+      let max_y = text.bounds().y1 + 2.0;
+      (
+        Line::new((5.0, min_y), (5.0, max_y)),
+        Line::new((5.0, max_y - fract * (max_y - min_y)), (5.0, max_y)),
+      )
+    }
+    cb_bar::Axis::Vertical => {
+      let min_x = text.bounds().x0 - 2.0;
+      let max_x = text.bounds().x1 + 2.0;
+      (
+        Line::new((min_x, 5.0), (max_x, 5.0)),
+        Line::new((min_x, 5.0), (min_x + fract * (max_x - min_x), 5.0)),
+      )
+    }
+  };
+
+  ctx.stroke(&background_line, background);
+  ctx.stroke(&level_line, level);
+}
+
 struct UpdateGroup {
   dirty: Vec<Weak<AtomicBool>>,
 }
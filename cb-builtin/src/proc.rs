@@ -8,7 +8,7 @@ use std::{
   time::{Duration, Instant},
 };
 
-use cb_bar::{Animation, Module, TextLayout, Updater};
+use cb_bar::{Animation, Axis, Module, TextLayout, Updater};
 use cb_core::{Color, Render, Text};
 use kurbo::{Line, Point};
 use peniko::Gradient;
@@ -91,8 +91,8 @@ pub struct CpuState {
 impl Files {
   pub fn new() -> Self {
     Files {
-      meminfo: File::open("/proc/meminfo").unwrap(),
-      stat:    File::open("/proc/stat").unwrap(),
+      meminfo: File::open(crate::system_path("/proc/meminfo")).unwrap(),
+      stat:    File::open(crate::system_path("/proc/stat")).unwrap(),
     }
   }
 
@@ -184,9 +184,10 @@ impl SystemInfo {
     SystemInfo { last_update: Instant::now(), last_state: None, curr_state, files }
   }
 
-  fn refresh(&mut self) {
+  /// Updates the state if it is older than `max_age`.
+  fn refresh(&mut self, max_age: Duration) {
     let now = Instant::now();
-    if now.duration_since(self.last_update) > Duration::from_secs(1) {
+    if now.duration_since(self.last_update) > max_age {
       self.update();
     }
   }
@@ -230,20 +231,28 @@ impl SystemInfo {
   }
 }
 
-#[derive(Clone)]
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Cpu {
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub secondary: Color,
+  #[serde(default = "crate::default_interval", deserialize_with = "cb_bar::config::duration")]
+  pub interval:  Duration,
 }
 struct CpuModule {
   spec: Cpu,
+  axis: Axis,
   text: Option<TextLayout>,
 
   usage: Vec<f64>,
 }
 
 impl From<Cpu> for Box<dyn Module> {
-  fn from(spec: Cpu) -> Self { Box::new(CpuModule { spec, text: None, usage: vec![] }) }
+  fn from(spec: Cpu) -> Self {
+    Box::new(CpuModule { spec, axis: Axis::Horizontal, text: None, usage: vec![] })
+  }
 }
 
 const MAX_PER_COL: usize = 8;
@@ -258,7 +267,19 @@ impl CpuModule {
 }
 
 impl Module for CpuModule {
-  fn updater(&self) -> Updater<'_> { Updater::Every(Duration::from_secs(1)) }
+  fn updater(&self) -> Updater<'_> { Updater::Every(self.spec.interval) }
+  fn tooltip(&self) -> Option<Text<'static>> {
+    let mut text = Text::new();
+    for (i, usage) in self.usage.iter().enumerate() {
+      if i != 0 {
+        text.push("\n", self.spec.secondary);
+      }
+      text.push(format_args!("cpu{i:<3}"), self.spec.secondary);
+      text.push(format_args!("{usage:>3.0}"), self.spec.primary);
+      text.push("%", self.spec.secondary);
+    }
+    Some(text)
+  }
   fn layout(&mut self, layout: &mut cb_bar::Layout) {
     SYS.with(|s| {
       let mut sys = s.borrow_mut();
@@ -266,7 +287,7 @@ impl Module for CpuModule {
         *sys = Some(SystemInfo::new());
       }
       let sys = sys.as_mut().unwrap();
-      sys.refresh();
+      sys.refresh(self.spec.interval);
       let state = sys.state();
 
       let mut text = Text::new();
@@ -274,7 +295,10 @@ impl Module for CpuModule {
       text.push("%", self.spec.secondary);
 
       self.usage = state.cpu.cpus.clone();
+      self.axis = layout.axis();
 
+      // In a vertical bar, the core grid is transposed, and placed above the
+      // text.
       layout.pad(10.0 + 6.0 * self.cols() as f64);
       self.text = Some(layout.layout_text(text, self.spec.primary));
       layout.pad(5.0);
@@ -292,24 +316,32 @@ impl Module for CpuModule {
         self.spec.primary,
       );
 
-      let min_y = text.bounds().y0 - 5.0;
-      let max_y = text.bounds().y1 + 5.0;
+      // The extent of each column of cores, across the bar.
+      let (min, max) = match self.axis {
+        Axis::Horizontal => (text.bounds().y0 - 5.0, text.bounds().y1 + 5.0),
+        Axis::Vertical => (text.bounds().x0 - 5.0, text.bounds().x1 + 5.0),
+      };
 
       if !self.usage.is_empty() {
         let (rows, cols) = self.row_cols();
-        let delta = (max_y - min_y) / (rows - 1) as f64;
+        let delta = (max - min) / (rows - 1) as f64;
 
         let mut cpu = 0;
         'outer: for i in 0..cols {
           for j in 0..rows {
-            let y = min_y + j as f64 * delta;
+            let across = min + j as f64 * delta;
+            let along = 5.0 + i as f64 * 6.0;
 
             if cpu >= self.usage.len() {
               break 'outer;
             }
 
+            let line = match self.axis {
+              Axis::Horizontal => Line::new((along, across), (along + 3.0, across)),
+              Axis::Vertical => Line::new((across, along), (across, along + 3.0)),
+            };
             ctx.stroke(
-              &Line::new((5.0 + i as f64 * 6.0, y), (5.0 + i as f64 * 6.0 + 3.0, y)),
+              &line,
               self.spec.secondary.lerp(
                 self.spec.primary,
                 (self.usage[cpu] / 100.0) as f32,
@@ -324,10 +356,15 @@ impl Module for CpuModule {
   }
 }
 
-#[derive(Clone)]
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Mem {
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub secondary: Color,
+  #[serde(default = "crate::default_interval", deserialize_with = "cb_bar::config::duration")]
+  pub interval:  Duration,
 }
 struct MemModule {
   spec:  Mem,
@@ -346,7 +383,7 @@ impl Module for MemModule {
     if self.hover.is_running() {
       Updater::Animation
     } else {
-      Updater::Every(Duration::from_secs(1))
+      Updater::Every(self.spec.interval)
     }
   }
 
@@ -361,7 +398,7 @@ impl Module for MemModule {
         *sys = Some(SystemInfo::new());
       }
       let sys = sys.as_mut().unwrap();
-      sys.refresh();
+      sys.refresh(self.spec.interval);
       let state = sys.state();
 
       let mut text = Text::new();
@@ -1,6 +1,6 @@
-use cb_bar::{Module, TextLayout, Updater};
-use cb_core::{Color, Render, Text, Waker};
-use kurbo::Line;
+use cb_bar::{Axis, Module, TextLayout, Updater};
+use cb_core::{Click, Color, MouseButton, Render, Scroll, Text, Waker};
+use kurbo::Point;
 use libpulse_sys as sys;
 use parking_lot::Mutex;
 use std::{
@@ -11,8 +11,12 @@ use std::{
 
 use crate::{Dirty, UpdateGroup};
 
+#[derive(Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Pulse {
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub primary:   Color,
+  #[serde(deserialize_with = "cb_bar::config::color")]
   pub secondary: Color,
 }
 
@@ -39,13 +43,34 @@ impl MainLoop {
     }
   }
 
-  pub fn run(&mut self) {
+  /// Runs the main loop until it fails, and runs every request that is sent
+  /// to it in between.
+  pub fn run(&mut self, ctx: &Context, requests: &crossbeam_channel::Receiver<Request>) {
     unsafe {
-      sys::pa_mainloop_run(self.pa, ptr::null_mut());
+      while sys::pa_mainloop_iterate(self.pa, 1, ptr::null_mut()) >= 0 {
+        for request in requests.try_iter() {
+          request(ctx);
+        }
+      }
     }
   }
 }
 
+/// A call to make with the context. The context isn't thread-safe, so only
+/// the thread running the main loop may use it, and everything else sends it
+/// requests.
+type Request = Box<dyn FnOnce(&Context) + Send>;
+
+/// Sends requests to the main loop thread.
+struct Handle {
+  requests: crossbeam_channel::Sender<Request>,
+  mainloop: *mut sys::pa_mainloop,
+}
+
+// `pa_mainloop_wakeup` is the one function of the main loop that may be called
+// from other threads.
+unsafe impl Send for Handle {}
+
 struct Context {
   pa: *mut sys::pa_context,
 }
@@ -190,7 +215,39 @@ impl Context {
         Some(cb),
         std::ptr::from_mut(ptr) as *mut c_void,
       );
-      sys::pa_context_subscribe(self.pa, sys::PA_SUBSCRIPTION_MASK_SINK, None, ptr::null_mut());
+      // Server events include changes to the default sink.
+      let mask = sys::PA_SUBSCRIPTION_MASK_SINK | sys::PA_SUBSCRIPTION_MASK_SERVER;
+      sys::pa_context_subscribe(self.pa, mask, None, ptr::null_mut());
+    }
+  }
+
+  pub fn set_sink_volume_by_name(&self, name: &CStr, volume: &Volume) {
+    unsafe {
+      let op = sys::pa_context_set_sink_volume_by_name(
+        self.pa,
+        name.as_ptr(),
+        &volume.pa,
+        None,
+        ptr::null_mut(),
+      );
+      if !op.is_null() {
+        sys::pa_operation_unref(op);
+      }
+    }
+  }
+
+  pub fn set_sink_mute_by_name(&self, name: &CStr, mute: bool) {
+    unsafe {
+      let op = sys::pa_context_set_sink_mute_by_name(
+        self.pa,
+        name.as_ptr(),
+        mute.into(),
+        None,
+        ptr::null_mut(),
+      );
+      if !op.is_null() {
+        sys::pa_operation_unref(op);
+      }
     }
   }
 
@@ -222,6 +279,12 @@ impl Context {
     dyn FnMut(SinkInfo),
     |info: *const sys::pa_sink_info| SinkInfo { pa: info }
   );
+  callback_list!(
+    get_sink_info_by_name(name: *const std::ffi::c_char),
+    pa_context_get_sink_info_by_name,
+    dyn FnMut(SinkInfo),
+    |info: *const sys::pa_sink_info| SinkInfo { pa: info }
+  );
   callback_list!(
     get_source_info_list(),
     pa_context_get_source_info_list,
@@ -473,17 +536,20 @@ struct SinkInfo {
 info! { SinkInfo =>
   /// Name of the sink
   name(&str);
+  /// Description of this sink
+  description(&str);
   /// Index of the sink
   index(u32);
   /// Volume of the sink
   volume(Volume);
   /// Some kind of "base" volume that refers to unamplified/unattenuated volume in the context of the output device.
   base_volume(u32);
+  /// Mute switch of the sink
+  mute(i32);
 
   // pa_sample_spec sample_spec;        /// Sample spec of this sink
   // pa_channel_map channel_map;        /// Channel map
   // uint32_t owner_module;             /// Index of the owning module of this sink, or PA_INVALID_INDEX.
-  // int mute;                          /// Mute switch of the sink
   // uint32_t monitor_source;           /// Index of the monitor source connected to this sink.
   // const char *monitor_source_name;   /// The name of the monitor source.
   // pa_usec_t latency;                 /// Length of queued audio in the output buffer.
@@ -525,6 +591,17 @@ impl Volume {
   pub fn channels(&self) -> u8 { self.pa.channels }
   pub fn values(&self) -> &[u32] { &self.pa.values[..self.channels() as usize] }
 
+  /// Changes the volume of every channel by `percent`, without raising it
+  /// past 100%, or past where it was for channels that are louder already.
+  pub fn step(&mut self, percent: f64) {
+    let delta = percent / 100.0 * f64::from(sys::PA_VOLUME_NORM);
+    let channels = self.channels() as usize;
+    for v in &mut self.pa.values[..channels] {
+      let max = f64::from((*v).max(sys::PA_VOLUME_NORM));
+      *v = (f64::from(*v) + delta).round().clamp(0.0, max) as u32;
+    }
+  }
+
   pub fn value_percents(&self) -> Vec<u32> {
     // According to `pactl`, this is how we find the percent:
     // ```
@@ -549,14 +626,21 @@ impl fmt::Debug for Volume {
   }
 }
 
-static CONTEXT: Mutex<Option<Arc<Context>>> = Mutex::new(None);
+static HANDLE: Mutex<Option<Handle>> = Mutex::new(None);
+
+/// Calls `f` with the context from the main loop thread, connecting first if
+/// this is the first call.
+fn with_context(f: impl FnOnce(&Context) + Send + 'static) {
+  let mut handle = HANDLE.lock();
+  let handle = handle.get_or_insert_with(connect);
 
-fn context() -> Arc<Context> {
-  let mut c = CONTEXT.lock();
-  if let Some(ref c) = *c {
-    return c.clone();
+  let _ = handle.requests.send(Box::new(f));
+  unsafe {
+    sys::pa_mainloop_wakeup(handle.mainloop);
   }
+}
 
+fn connect() -> Handle {
   let mut l = MainLoop::new();
   let props = PropList::new();
   let mut ctx = Context::new(&mut l, &props);
@@ -575,36 +659,60 @@ fn context() -> Arc<Context> {
 
   ctx.connect();
 
+  let (requests, requests_rx) = crossbeam_channel::unbounded();
+  let mainloop = l.pa;
+
   std::thread::spawn(move || {
-    l.run();
+    l.run(&ctx, &requests_rx);
+    eprintln!("pulse: main loop stopped");
+    // Other threads may still wake the main loop, so it's never freed.
+    std::mem::forget(l);
   });
 
   ready_rx.recv().unwrap();
 
-  let ctx = Arc::new(ctx);
-
-  *c = Some(ctx.clone());
-  ctx
+  Handle { requests, mainloop }
 }
 
 struct PulseModule {
   spec:   Pulse,
+  axis:   Axis,
   text:   Option<TextLayout>,
   dirty:  Dirty,
   volume: u32,
+  muted:  bool,
 }
 
 impl From<Pulse> for Box<dyn Module> {
   fn from(spec: Pulse) -> Self {
-    Box::new(PulseModule { spec, text: None, dirty: UPDATERS.lock().add(), volume: 0 })
+    Box::new(PulseModule {
+      spec,
+      axis: Axis::Horizontal,
+      text: None,
+      dirty: UPDATERS.lock().add(),
+      volume: 0,
+      muted: false,
+    })
   }
 }
 
-static STATE: Mutex<PulseState> = Mutex::new(PulseState { volume: 0 });
+static STATE: Mutex<PulseState> =
+  Mutex::new(PulseState { volume: 0, muted: false, sink: String::new() });
 static UPDATERS: Mutex<UpdateGroup> = Mutex::new(UpdateGroup::new());
 
 struct PulseState {
   volume: u32,
+  muted:  bool,
+  /// The description of the sink, like "Built-in Audio Analog Stereo".
+  sink:   String,
+}
+
+impl PulseState {
+  fn update(&mut self, info: &SinkInfo) {
+    self.volume = info.volume().value_percents()[0];
+    self.muted = info.mute() != 0;
+    self.sink = info.description().to_string();
+  }
 }
 
 fn set_callback(waker: &Arc<Waker>) {
@@ -613,39 +721,91 @@ fn set_callback(waker: &Arc<Waker>) {
   static SETUP: AtomicBool = AtomicBool::new(false);
 
   if !SETUP.swap(true, Ordering::SeqCst) {
-    let ctx = context();
     let waker = waker.clone();
 
-    let w = waker.clone();
-    context().get_sink_info_list(move |info| {
-      STATE.lock().volume = info.volume().value_percents()[0];
-      UPDATERS.lock().mark_dirty();
-      w.wake();
-    });
+    with_context(move |ctx| {
+      read_default_sink(ctx, waker.clone());
 
-    ctx.set_on_change(move || {
-      let w = waker.clone();
-      context().get_sink_info_list(move |info| {
-        STATE.lock().volume = info.volume().value_percents()[0];
-        UPDATERS.lock().mark_dirty();
-        w.wake();
+      ctx.set_on_change(move || {
+        let waker = waker.clone();
+        with_context(move |ctx| read_default_sink(ctx, waker));
       });
     });
   }
 }
 
+/// Reads the state of the default sink, which is the one that scrolling and
+/// muting act on.
+fn read_default_sink(ctx: &Context, waker: Arc<Waker>) {
+  ctx.get_sink_info_by_name(c"@DEFAULT_SINK@".as_ptr(), move |info| {
+    STATE.lock().update(&info);
+    UPDATERS.lock().mark_dirty();
+    waker.wake();
+  });
+}
+
+/// How much one scroll wheel click changes the volume, in percent.
+const SCROLL_STEP: f64 = 5.0;
+
 impl Module for PulseModule {
   fn updater(&self) -> Updater<'_> { Updater::Atomic(self.dirty.get()) }
+
+  fn on_click(&mut self, _: Point, click: Click) {
+    if click.is_press(MouseButton::Middle) {
+      // Toggle the default sink as it is now, rather than as it was drawn.
+      with_context(move |ctx| {
+        ctx.get_sink_info_by_name(c"@DEFAULT_SINK@".as_ptr(), move |info| {
+          let mute = info.mute() == 0;
+          with_context(move |ctx| ctx.set_sink_mute_by_name(c"@DEFAULT_SINK@", mute));
+        });
+      });
+    }
+  }
+
+  fn on_scroll(&mut self, _: Point, scroll: Scroll) {
+    // Scrolling up raises the volume.
+    let percent = -scroll.steps_y() * SCROLL_STEP;
+    if percent == 0.0 {
+      return;
+    }
+
+    with_context(move |ctx| {
+      ctx.get_sink_info_by_name(c"@DEFAULT_SINK@".as_ptr(), move |info| {
+        let mut volume = info.volume();
+        volume.step(percent);
+        with_context(move |ctx| ctx.set_sink_volume_by_name(c"@DEFAULT_SINK@", &volume));
+      });
+    });
+  }
+
+  fn tooltip(&self) -> Option<Text<'static>> {
+    let state = STATE.lock();
+
+    let mut text = Text::new();
+    text.push(&state.sink, self.spec.primary);
+    if state.muted {
+      text.push(" (muted)", self.spec.secondary);
+    }
+    Some(text)
+  }
+
   fn layout(&mut self, layout: &mut cb_bar::Layout) {
     layout.pad(10.0);
 
     set_callback(layout.waker);
     self.dirty.clear();
 
-    self.volume = STATE.lock().volume;
+    let state = STATE.lock();
+    self.volume = state.volume;
+    self.muted = state.muted;
+    drop(state);
+    self.axis = layout.axis();
+
+    // Gray out the volume while muted.
+    let color = if self.muted { self.spec.secondary } else { self.spec.primary };
 
     let mut text = Text::new();
-    text.push(format_args!("{}", self.volume), self.spec.primary);
+    text.push(format_args!("{}", self.volume), color);
     text.push("%", self.spec.secondary);
 
     self.text = Some(layout.layout_text(text, self.spec.primary));
@@ -656,16 +816,43 @@ impl Module for PulseModule {
     if let Some(text) = &self.text {
       ctx.draw(text);
 
-      let min_y = text.bounds().y0 - 2.0;
-      let max_y = text.bounds().y1 + 2.0;
-      ctx.stroke(&Line::new((5.0, min_y), (5.0, max_y)), self.spec.secondary);
-
       let fract = self.volume as f64 / 100.0;
-
-      ctx.stroke(
-        &Line::new((5.0, max_y - fract * (max_y - min_y)), (5.0, max_y)),
-        self.spec.primary,
-      );
+      crate::draw_level(ctx, text, self.axis, fract, self.spec.secondary, self.spec.primary);
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn volume(values: &[u32]) -> Volume {
+    let mut pa = sys::pa_cvolume { channels: values.len() as u8, values: [0; 32] };
+    pa.values[..values.len()].copy_from_slice(values);
+    Volume { pa }
+  }
+
+  #[test]
+  fn step_stops_at_100_percent() {
+    let norm = sys::PA_VOLUME_NORM;
+
+    let mut v = volume(&[norm * 98 / 100, norm / 2]);
+    v.step(5.0);
+    assert_eq!(v.value_percents(), [100, 55]);
+
+    v.step(-60.0);
+    assert_eq!(v.value_percents(), [40, 0]);
+  }
+
+  #[test]
+  fn step_keeps_amplified_volume() {
+    let norm = sys::PA_VOLUME_NORM;
+
+    let mut v = volume(&[norm * 130 / 100]);
+    v.step(5.0);
+    assert_eq!(v.value_percents(), [130]);
+
+    v.step(-5.0);
+    assert_eq!(v.value_percents(), [125]);
+  }
+}
@@ -0,0 +1,114 @@
+//! Draws the builtin modules with the system files in `tests/root`, and
+//! compares them against the references in `tests/snapshots`. Run with
+//! `CB_UPDATE_SNAPSHOTS=1` to update them.
+//!
+//! The clock, hypr and pulse modules aren't covered, as they show the current
+//! time, or talk to a running compositor or sound server.
+
+use std::time::Duration;
+
+use cb_bar::{
+  Axis, Module,
+  snapshot::{Snapshot, primary, secondary},
+};
+use cb_builtin::*;
+
+fn snapshot() -> Snapshot {
+  cb_builtin::set_system_root(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/root"));
+
+  Snapshot::fixture(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"))
+}
+
+/// Draws `module`, in a vertical bar if `name` ends in `_vertical`.
+#[track_caller]
+fn check(name: &str, module: Box<dyn Module>) {
+  let (axis, width, height) = if name.ends_with("_vertical") {
+    (Axis::Vertical, 50, 100)
+  } else {
+    (Axis::Horizontal, 150, 30)
+  };
+  if let Err(e) = snapshot().axis(axis).check_module(name, module, width, height) {
+    panic!("{e}");
+  }
+}
+
+fn battery() -> Box<dyn Module> {
+  Battery { primary: primary(), secondary: secondary(), batteries: vec![] }.into()
+}
+
+fn backlight(target: Target) -> Box<dyn Module> {
+  Backlight { primary: primary(), secondary: secondary(), target, device: None }.into()
+}
+
+fn temp(sensors: Vec<Selector>, warning: Option<f32>) -> Box<dyn Module> {
+  Temp {
+    primary: primary(),
+    secondary: secondary(),
+    interval: Duration::from_secs(1),
+    sensors,
+    aggregate: Aggregate::Max,
+    warning,
+    critical: None,
+    warning_color: cb_core::oklch(0.75, 0.17, 70.0),
+    critical_color: cb_core::oklch(0.65, 0.22, 25.0),
+  }
+  .into()
+}
+
+fn chip(name: &str) -> Selector { Selector { chip: Some(name.into()), ..Default::default() } }
+
+#[test]
+fn battery_discharging() { check("battery", battery()); }
+
+#[test]
+fn battery_vertical() { check("battery_vertical", battery()); }
+
+#[test]
+fn backlight_screen() { check("backlight", backlight(Target::Screen)); }
+
+#[test]
+fn backlight_missing() { check("backlight_missing", backlight(Target::Keyboard)); }
+
+#[test]
+fn temp_cpu() { check("temp", temp(vec![], None)); }
+
+#[test]
+fn temp_warning() { check("temp_warning", temp(vec![chip("nct6798")], Some(30.0))); }
+
+#[test]
+fn fan() {
+  let fan = Fan {
+    primary:   primary(),
+    secondary: secondary(),
+    interval:  Duration::from_secs(1),
+    sensors:   vec![],
+    aggregate: Aggregate::Max,
+  };
+  check("fan", fan.into());
+}
+
+/// The package power of the CPU isn't covered, as it depends on the time
+/// between two readings.
+#[test]
+fn power() {
+  let power = Power {
+    primary:   primary(),
+    secondary: secondary(),
+    interval:  Duration::from_secs(1),
+    sensors:   vec![chip("amdgpu")],
+    aggregate: Aggregate::Sum,
+  };
+  check("power", power.into());
+}
+
+#[test]
+fn cpu() {
+  let cpu = Cpu { primary: primary(), secondary: secondary(), interval: Duration::from_secs(1) };
+  check("cpu", cpu.into());
+}
+
+#[test]
+fn mem() {
+  let mem = Mem { primary: primary(), secondary: secondary(), interval: Duration::from_secs(1) };
+  check("mem", mem.into());
+}
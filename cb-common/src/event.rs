@@ -0,0 +1,174 @@
+//! Event sources that are serviced by the main loop, so that modules don't
+//! need their own threads to wait on sockets, timers or signals.
+
+use std::{
+  any::{Any, TypeId},
+  cell::RefCell,
+  collections::HashMap,
+  io,
+  os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+  rc::Rc,
+  time::Instant,
+};
+
+/// What to do with a source after its callback runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostAction {
+  Continue,
+  Remove,
+}
+
+/// The event sources registered by modules. All callbacks run on the main
+/// thread, right before checking if any bars need to be drawn.
+///
+/// Sources may be added from within callbacks.
+#[derive(Default)]
+pub struct EventLoop {
+  fds:    RefCell<Vec<Box<dyn FdSource>>>,
+  timers: RefCell<Vec<Timer>>,
+  data:   RefCell<HashMap<TypeId, Rc<dyn Any>>>,
+}
+
+trait FdSource {
+  fn fd(&self) -> RawFd;
+  fn ready(&mut self) -> PostAction;
+}
+
+struct Fd<T, F> {
+  source:   T,
+  callback: F,
+}
+
+impl<T: AsFd, F: FnMut(&mut T) -> PostAction> FdSource for Fd<T, F> {
+  fn fd(&self) -> RawFd { self.source.as_fd().as_raw_fd() }
+  fn ready(&mut self) -> PostAction { (self.callback)(&mut self.source) }
+}
+
+struct Timer {
+  deadline: Instant,
+  callback: Box<dyn FnMut() -> Option<Instant>>,
+}
+
+impl EventLoop {
+  pub fn new() -> Self { EventLoop::default() }
+
+  /// Calls `callback` whenever `source` is readable, or has hung up. The
+  /// source should be non-blocking, and the callback should read everything
+  /// that's available.
+  pub fn add_fd<T: AsFd + 'static>(
+    &self,
+    source: T,
+    callback: impl FnMut(&mut T) -> PostAction + 'static,
+  ) {
+    self.fds.borrow_mut().push(Box::new(Fd { source, callback }));
+  }
+
+  /// Returns the `T` that belongs to this loop, creating it the first time.
+  /// This lets modules keep state about the sources they've added, for as
+  /// long as the loop is around.
+  pub fn data<T: Default + 'static>(&self) -> Rc<T> {
+    let data = self
+      .data
+      .borrow_mut()
+      .entry(TypeId::of::<T>())
+      .or_insert_with(|| Rc::new(T::default()))
+      .clone();
+    data.downcast().unwrap()
+  }
+
+  /// Calls `callback` once `deadline` has passed. The callback returns the
+  /// next time it should be called, or `None` to remove the timer.
+  pub fn add_timer(&self, deadline: Instant, callback: impl FnMut() -> Option<Instant> + 'static) {
+    self.timers.borrow_mut().push(Timer { deadline, callback: Box::new(callback) });
+  }
+
+  /// Calls `callback` whenever the process receives `signal`, instead of
+  /// running its default action.
+  ///
+  /// The signal is only blocked on the calling thread, so this should be
+  /// called from the main thread before any other threads are spawned.
+  pub fn add_signal(
+    &self,
+    signal: libc::c_int,
+    mut callback: impl FnMut() -> PostAction + 'static,
+  ) -> io::Result<()> {
+    let fd = unsafe {
+      let mut mask = std::mem::zeroed::<libc::sigset_t>();
+      libc::sigemptyset(&mut mask);
+      libc::sigaddset(&mut mask, signal);
+
+      // Signals only show up in a signalfd if they are blocked.
+      if libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      let fd = libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      OwnedFd::from_raw_fd(fd)
+    };
+
+    self.add_fd(fd, move |fd| {
+      let mut info = unsafe { std::mem::zeroed::<libc::signalfd_siginfo>() };
+      let size = std::mem::size_of::<libc::signalfd_siginfo>();
+
+      // Several signals may have arrived since the last poll.
+      while unsafe { libc::read(fd.as_raw_fd(), (&raw mut info).cast(), size) } == size as isize {
+        if callback() == PostAction::Remove {
+          return PostAction::Remove;
+        }
+      }
+      PostAction::Continue
+    });
+
+    Ok(())
+  }
+
+  /// Returns the earliest deadline of all the timers.
+  pub fn deadline(&self) -> Option<Instant> {
+    self.timers.borrow().iter().map(|timer| timer.deadline).min()
+  }
+
+  /// Adds a `pollfd` for every fd source to `fds`. The same entries must be
+  /// passed to `dispatch` after polling.
+  pub fn fill_pollfds(&self, fds: &mut Vec<libc::pollfd>) {
+    fds.extend(self.fds.borrow().iter().map(|source| libc::pollfd {
+      fd:      source.fd(),
+      events:  libc::POLLIN,
+      revents: 0,
+    }));
+  }
+
+  /// Runs the callbacks of every fd source that is ready in `fds`, and every
+  /// timer that has expired.
+  pub fn dispatch(&self, fds: &[libc::pollfd]) {
+    // Take the sources out while their callbacks run, so that callbacks can
+    // add new ones.
+    let mut sources = self.fds.take();
+    let mut ready = fds.iter().map(|fd| fd.revents != 0);
+    sources.retain_mut(|source| {
+      !ready.next().unwrap_or(false) || source.ready() == PostAction::Continue
+    });
+    sources.append(&mut self.fds.borrow_mut());
+    *self.fds.borrow_mut() = sources;
+
+    let now = Instant::now();
+    let mut timers = self.timers.take();
+    timers.retain_mut(|timer| {
+      if timer.deadline > now {
+        return true;
+      }
+
+      match (timer.callback)() {
+        Some(deadline) => {
+          timer.deadline = deadline;
+          true
+        }
+        None => false,
+      }
+    });
+    timers.append(&mut self.timers.borrow_mut());
+    *self.timers.borrow_mut() = timers;
+  }
+}
@@ -1,21 +1,88 @@
 use std::{
   collections::HashMap,
   os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
-  sync::Arc,
+  rc::Rc,
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+  time::{Duration, Instant},
 };
 
+mod event;
+
+pub use event::{EventLoop, PostAction};
+
 pub struct Gpu<A> {
+  /// The GPU to draw with, or `None` if bars are drawn on the CPU.
+  wgpu: Option<Wgpu>,
+
+  bars: HashMap<BarId, Bar>,
+
+  app:    A,
+  cursor: Option<(f64, f64)>,
+
+  pub waker:  Option<Arc<Waker>>,
+  pub events: Option<Rc<EventLoop>>,
+}
+
+struct Wgpu {
   instance: wgpu::Instance,
   adapter:  wgpu::Adapter,
   device:   wgpu::Device,
   queue:    wgpu::Queue,
 
-  bars: HashMap<BarId, Bar>,
+  /// Set once the device is lost, after which it has to be created again.
+  lost: Arc<AtomicBool>,
+}
 
-  app:    A,
-  cursor: Option<(f64, f64)>,
+/// The environment variable that picks the renderer, either `gpu` or `cpu`.
+/// By default, the CPU is only used if there's no usable GPU.
+const RENDERER_VAR: &str = "CB_RENDERER";
+
+/// Returns the renderer picked with `RENDERER_VAR`, or `None` to pick one
+/// automatically.
+fn renderer() -> Option<&'static str> {
+  let value = std::env::var(RENDERER_VAR).ok()?;
+  match value.as_str() {
+    "" => None,
+    "cpu" => Some("cpu"),
+    "gpu" => Some("gpu"),
+    _ => {
+      eprintln!("unknown {RENDERER_VAR} `{value}`, expected `gpu` or `cpu`");
+      None
+    }
+  }
+}
+
+/// How long a bar waits before it's drawn again, after there was nothing to
+/// draw it into.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
 
-  pub waker: Option<Arc<Waker>>,
+/// What bars are drawn with.
+#[derive(Clone, Copy)]
+pub enum Device<'a> {
+  Gpu(&'a wgpu::Device, &'a wgpu::Queue),
+  /// There's no usable GPU, so bars are drawn on the CPU.
+  Cpu,
+}
+
+/// What a frame is drawn into.
+pub enum Target<'a> {
+  Texture(&'a wgpu::Texture),
+  /// Premultiplied pixels in the `ARGB8888` format of `wl_shm`, which is BGRA
+  /// in memory. Rows are tightly packed.
+  Pixels(&'a mut [u8]),
+}
+
+/// Shows the frames drawn by [`Gpu::render`].
+pub trait Present {
+  /// Returns a buffer to draw the bar `id` into on the CPU, with the given
+  /// size in pixels, or `None` if there isn't one available.
+  fn buffer(&mut self, id: BarId, width: u32, height: u32) -> Option<&mut [u8]>;
+  /// Called once the bar `id` is drawn, with the regions that changed. Bars
+  /// drawn on the GPU are presented right after this.
+  fn present(&mut self, id: BarId, damage: &[Damage]);
 }
 
 pub struct Waker {
@@ -55,80 +122,452 @@ impl Waker {
 pub trait App {
   type Config;
 
-  fn new(config: Self::Config, device: &wgpu::Device) -> Self;
+  fn new(config: Self::Config, device: Device) -> Self;
   fn waker(&self) -> Option<Arc<Waker>>;
+  /// Returns the event sources to service from the main loop.
+  fn events(&self) -> Option<Rc<EventLoop>>;
+  /// Called on every iteration of the main loop, before checking if any bars
+  /// need to be rendered. Returns true if the geometry of any bar may have
+  /// changed.
+  fn update(&mut self) -> bool;
+  /// Returns the geometry of the bar on `output`, or `None` if `output`
+  /// shouldn't get a bar at all.
+  fn geometry(&self, output: &OutputInfo) -> Option<Geometry>;
+  #[allow(clippy::too_many_arguments)]
   fn create_bar(
     &mut self,
     id: BarId,
-    device: &wgpu::Device,
+    output: &OutputInfo,
+    device: Device,
+    format: wgpu::TextureFormat,
+    scale: f64,
+    width: u32,
+    height: u32,
+  );
+  /// Returns the popup the bar `id` wants to open, if any. If one is
+  /// returned, it will be created with the id `popup`. This is checked on
+  /// every iteration of the main loop.
+  fn take_popup(&mut self, id: BarId, popup: BarId) -> Option<PopupRequest>;
+  /// Returns true if the popup `id` should be closed.
+  fn popup_closed(&self, id: BarId) -> bool;
+  /// Creates the popup `id`, which was returned from `take_popup`. After this,
+  /// `id` is treated just like a bar.
+  fn create_popup(
+    &mut self,
+    id: BarId,
+    device: Device,
     format: wgpu::TextureFormat,
     scale: f64,
     width: u32,
     height: u32,
   );
-  fn set_size(&mut self, id: BarId, device: &wgpu::Device, factor: f64, width: u32, height: u32);
+  /// Called when the surface of the bar `id` is gone, either because its
+  /// output was unplugged, or the compositor closed it. The same `id` may be
+  /// passed to `create_bar` again later. This is also called when a popup is
+  /// closed.
+  fn destroy_bar(&mut self, id: BarId);
+  fn set_size(&mut self, id: BarId, device: Device, factor: f64, width: u32, height: u32);
+  /// Called after the GPU was lost, with the device that replaced it. Anything
+  /// created on the old device has to be created again. If that fails, this
+  /// is called again with `Device::Cpu`, which must not fail.
+  fn set_device(&mut self, device: Device) -> Result<(), String>;
   fn dirty(&self, id: BarId) -> bool;
+  /// Returns when the bar `id` will next become dirty, if it doesn't get woken
+  /// up by the waker first. The main loop sleeps until the earliest deadline.
+  fn deadline(&self, id: BarId) -> Option<std::time::Instant>;
   fn move_mouse(&mut self, id: BarId, pos: Option<(f64, f64)>);
-  fn click_mouse(&mut self, id: BarId, pos: (f64, f64));
-  fn draw(&mut self, id: BarId, device: &wgpu::Device, queue: &wgpu::Queue, output: &wgpu::Texture);
+  fn click_mouse(&mut self, id: BarId, pos: (f64, f64), click: Click);
+  fn scroll_mouse(&mut self, id: BarId, pos: (f64, f64), scroll: Scroll);
+  /// Draws the bar `id` into `output`, and returns the regions that changed
+  /// since the last frame.
+  fn draw(&mut self, id: BarId, device: Device, output: Target) -> Vec<Damage>;
+}
+
+/// A region of a bar that changed, in buffer pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Damage {
+  pub x:      i32,
+  pub y:      i32,
+  pub width:  i32,
+  pub height: i32,
 }
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct BarId(u32);
 
+/// Where and how a bar is placed on its output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Geometry {
+  pub edge:      Edge,
+  /// The height of the bar, or the width for bars on the left or right edge,
+  /// in logical pixels.
+  pub size:      u32,
+  /// How much of the bar windows stay clear of.
+  pub exclusive: Exclusive,
+  pub layer:     Layer,
+  pub margin:    Margin,
+  /// The namespace of the layer surface, which compositors use to apply layer
+  /// rules. This cannot be changed once the bar is created.
+  pub namespace: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+  #[default]
+  Top,
+  Bottom,
+  Left,
+  Right,
+}
+
+/// The space windows leave free for the bar, which is `true` for the whole
+/// bar, `false` to let windows cover it, or a size in logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Exclusive {
+  All(bool),
+  Size(u32),
+}
+
+impl Exclusive {
+  /// Returns the exclusive zone of a bar that is `size` pixels wide.
+  pub fn zone(self, size: u32) -> u32 {
+    match self {
+      Exclusive::All(true) => size,
+      Exclusive::All(false) => 0,
+      Exclusive::Size(zone) => zone.min(size),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+  #[default]
+  Background,
+  Bottom,
+  Top,
+  Overlay,
+}
+
+/// Space between the bar and the edges of the output, in logical pixels. Only
+/// the margins on the sides the bar is anchored to have any effect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Margin {
+  pub top:    i32,
+  pub right:  i32,
+  pub bottom: i32,
+  pub left:   i32,
+}
+
+impl Default for Geometry {
+  fn default() -> Self {
+    Geometry {
+      edge:      Edge::Top,
+      size:      40,
+      // Modules are centered in the whole bar, and windows may cover the last
+      // 10 pixels, where there's rarely anything drawn.
+      exclusive: Exclusive::Size(30),
+      layer:     Layer::Background,
+      margin:    Margin::default(),
+      namespace: "correct-bar".into(),
+    }
+  }
+}
+
+/// Information about the output a bar is shown on. Any of these may be empty
+/// if the compositor doesn't send them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputInfo {
+  /// The connector name, like `DP-1` or `eDP-1`.
+  pub name:        String,
+  pub description: String,
+  pub make:        String,
+  pub model:       String,
+}
+
+/// A popup that a bar wants to open. All values are in logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PopupRequest {
+  /// The area the popup is anchored to, relative to the bar.
+  pub anchor_x:      i32,
+  pub anchor_y:      i32,
+  pub anchor_width:  i32,
+  pub anchor_height: i32,
+
+  pub width:  u32,
+  pub height: u32,
+
+  pub kind:   PopupKind,
+  /// The serial of the press that opened a menu, which the menu grabs the
+  /// pointer with. Menus without one aren't opened.
+  pub serial: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PopupKind {
+  /// A popup opened by a click, which grabs the pointer, and closes when
+  /// clicking outside of it.
+  Menu,
+  /// A popup that ignores the pointer, and stays open until `popup_closed`
+  /// returns true.
+  Tooltip,
+}
+
+/// A mouse button being pressed or released.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Click {
+  pub button:    MouseButton,
+  /// True for a press, false for a release.
+  pub pressed:   bool,
+  pub modifiers: Modifiers,
+  /// The number of presses in quick succession, so 2 is a double click. A
+  /// release has the same count as the press before it.
+  pub count:     u32,
+  /// Identifies the event to the compositor, which a menu opened by this
+  /// press needs.
+  pub serial:    u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+  Left,
+  Right,
+  Middle,
+  Back,
+  Forward,
+  /// Any other button, as a linux input event code.
+  Other(u32),
+}
+
+/// The keyboard modifiers held during a click. Compositors only send these to
+/// the focused client, so they may be missing when a bar isn't focused.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+  pub shift: bool,
+  pub ctrl:  bool,
+  pub alt:   bool,
+  pub logo:  bool,
+}
+
+impl Click {
+  /// Returns true if this is a press of `button`.
+  pub fn is_press(&self, button: MouseButton) -> bool { self.pressed && self.button == button }
+}
+
+impl MouseButton {
+  /// Converts a linux input event code, like `BTN_LEFT`, into a button.
+  pub fn from_code(code: u32) -> Self {
+    match code {
+      0x110 => MouseButton::Left,
+      0x111 => MouseButton::Right,
+      0x112 => MouseButton::Middle,
+      0x113 => MouseButton::Back,
+      0x114 => MouseButton::Forward,
+      _ => MouseButton::Other(code),
+    }
+  }
+}
+
+/// A scroll from the mouse wheel or touchpad. Positive values scroll down or
+/// to the right.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scroll {
+  /// The distance scrolled, in logical pixels.
+  pub dx:      f64,
+  pub dy:      f64,
+  /// The distance scrolled in wheel clicks. High resolution wheels may scroll
+  /// by fractions of a click. These are zero for touchpads.
+  pub steps_x: f64,
+  pub steps_y: f64,
+}
+
+impl Scroll {
+  /// How many pixels a touchpad must scroll to count as one wheel click.
+  const PIXELS_PER_STEP: f64 = 10.0;
+
+  /// Returns the horizontal distance in wheel clicks, falling back to the
+  /// pixel distance for touchpads.
+  pub fn steps_x(&self) -> f64 {
+    if self.steps_x != 0.0 { self.steps_x } else { self.dx / Self::PIXELS_PER_STEP }
+  }
+
+  /// Returns the vertical distance in wheel clicks, falling back to the pixel
+  /// distance for touchpads.
+  pub fn steps_y(&self) -> f64 {
+    if self.steps_y != 0.0 { self.steps_y } else { self.dy / Self::PIXELS_PER_STEP }
+  }
+
+  pub fn is_empty(&self) -> bool { *self == Scroll::default() }
+}
+
 pub struct Bar {
-  surface:        wgpu::Surface<'static>,
-  surface_config: wgpu::SurfaceConfiguration,
+  /// The surface this bar is presented to. Bars drawn on the CPU don't have
+  /// one, and are presented by the backend instead.
+  surface:       Option<Surface>,
+  /// The size of the bar, in buffer pixels.
+  width:         u32,
+  height:        u32,
+  /// Set after presenting a frame, until the compositor is ready for the next
+  /// one.
+  frame_pending: bool,
+  /// Set to draw the bar on the next call to `render`, even if it isn't dirty.
+  force_draw:    bool,
+  /// Set when there was nothing to draw the last frame into, so that the bar
+  /// isn't drawn again until then, instead of trying over and over.
+  retry_at:      Option<Instant>,
 
   pub scale: f64,
 }
 
+impl Bar {
+  /// Returns whether this bar is waiting to try drawing again at `now`.
+  fn retrying(&self, now: Instant) -> bool { self.retry_at.is_some_and(|at| now < at) }
+}
+
+struct Surface {
+  surface: wgpu::Surface<'static>,
+  config:  wgpu::SurfaceConfiguration,
+  /// The last error from getting a texture, so that it's only logged once.
+  error:   Option<wgpu::SurfaceError>,
+}
+
 impl BarId {
+  /// Set on the ids of popups, so that they never collide with bars.
+  const POPUP: u32 = 1 << 31;
+
   pub fn new(id: u32) -> Self { BarId(id) }
+  pub fn popup(id: u32) -> Self { BarId(id | Self::POPUP) }
+
+  pub fn is_popup(&self) -> bool { self.0 & Self::POPUP != 0 }
 }
 
 impl<A: App> Gpu<A> {
+  /// Creates a GPU to draw bars with. If there's no usable GPU, this falls back
+  /// to drawing on the CPU.
   pub fn new(config: A::Config) -> Self {
-    let instance = wgpu::Instance::new(&Default::default());
+    let wgpu = match renderer() {
+      Some("cpu") => None,
+      Some("gpu") => Some(Wgpu::new(false).expect("no usable GPU")),
+      _ => match Wgpu::new(false) {
+        Ok(wgpu) => Some(wgpu),
+        Err(e) => {
+          eprintln!("no usable GPU, drawing on the CPU instead: {e}");
+          None
+        }
+      },
+    };
 
-    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-      power_preference:       wgpu::PowerPreference::default(),
-      compatible_surface:     None,
-      force_fallback_adapter: false,
-    }))
-    .unwrap();
+    Self::from_wgpu(config, wgpu)
+  }
+
+  /// Creates a GPU that only renders into textures, for use without a display.
+  /// This falls back to the software adapter if there's no hardware one, and
+  /// then to the CPU.
+  pub fn new_offscreen(config: A::Config) -> Self {
+    let wgpu = match renderer() {
+      Some("cpu") => None,
+      _ => Wgpu::new(false).or_else(|_| Wgpu::new(true)).ok(),
+    };
+
+    Self::from_wgpu(config, wgpu)
+  }
+
+  /// Creates a GPU that only renders into textures, on a hardware or software
+  /// adapter. Returns `None` if there's neither.
+  pub fn new_offscreen_gpu(config: A::Config) -> Option<Self> {
+    let wgpu = Wgpu::new(false).or_else(|_| Wgpu::new(true)).ok()?;
+    Some(Self::from_wgpu(config, Some(wgpu)))
+  }
 
-    let (device, queue) = pollster::block_on(adapter.request_device(&Default::default())).unwrap();
+  /// Creates a "GPU" that always draws on the CPU, which gives the same pixels
+  /// on every machine.
+  pub fn new_cpu(config: A::Config) -> Self { Self::from_wgpu(config, None) }
 
-    let app = A::new(config, &device);
+  fn from_wgpu(config: A::Config, wgpu: Option<Wgpu>) -> Self {
+    let app = A::new(config, device(&wgpu));
     let waker = app.waker();
+    let events = app.events();
 
-    Gpu { instance, adapter, device, queue, bars: HashMap::new(), app, cursor: None, waker }
+    if let Some(wgpu) = &wgpu {
+      wgpu.watch_lost(waker.clone());
+    }
+
+    Gpu { wgpu, bars: HashMap::new(), app, cursor: None, waker, events }
   }
 
-  pub fn instance(&self) -> &wgpu::Instance { &self.instance }
+  /// Returns the wgpu instance, or `None` if bars are drawn on the CPU.
+  pub fn instance(&self) -> Option<&wgpu::Instance> { self.wgpu.as_ref().map(|w| &w.instance) }
+  pub fn device(&self) -> Device<'_> { device(&self.wgpu) }
   pub fn bar(&self, id: BarId) -> Option<&Bar> { self.bars.get(&id) }
   pub fn bar_mut(&mut self, id: BarId) -> Option<&mut Bar> { self.bars.get_mut(&id) }
 
+  pub fn geometry(&self, output: &OutputInfo) -> Option<Geometry> { self.app.geometry(output) }
+
+  /// Adds the bar `id` on `output`. `surface` must be set, unless bars are
+  /// drawn on the CPU.
   pub fn add_surface(
     &mut self,
     id: BarId,
-    surface: wgpu::Surface<'static>,
+    output: &OutputInfo,
+    surface: Option<wgpu::Surface<'static>>,
     scale: f64,
     width: u32,
     height: u32,
   ) {
-    let surface_caps = surface.get_capabilities(&self.adapter);
-    // Shader code in this tutorial assumes an sRGB surface texture. Using a
-    // different one will result in all the colors coming out darker. If you
-    // want to support non sRGB surfaces, you'll need to account for that when
-    // drawing to the frame.
-    let surface_format = surface_caps
-      .formats
-      .iter()
-      .find(|f| !f.is_srgb())
-      .copied()
-      .expect("sRGB surface not supported");
+    let format = self.configure_surface(id, surface, scale, width, height);
+    self.app.create_bar(id, output, device(&self.wgpu), format, scale, width, height);
+  }
+
+  /// Adds the surface for a popup, which was requested from `take_popup`.
+  pub fn add_popup_surface(
+    &mut self,
+    id: BarId,
+    surface: Option<wgpu::Surface<'static>>,
+    scale: f64,
+    width: u32,
+    height: u32,
+  ) {
+    let format = self.configure_surface(id, surface, scale, width, height);
+    self.app.create_popup(id, device(&self.wgpu), format, scale, width, height);
+  }
+
+  fn configure_surface(
+    &mut self,
+    id: BarId,
+    surface: Option<wgpu::Surface<'static>>,
+    scale: f64,
+    width: u32,
+    height: u32,
+  ) -> wgpu::TextureFormat {
+    let (width, height) = ((width as f64 * scale) as u32, (height as f64 * scale) as u32);
+    let bar = |surface| Bar {
+      surface,
+      width,
+      height,
+      frame_pending: false,
+      force_draw: false,
+      retry_at: None,
+      scale,
+    };
+
+    let (Some(wgpu), Some(surface)) = (&self.wgpu, surface) else {
+      // This matches the layout of `Target::Pixels`.
+      self.bars.insert(id, bar(None));
+      return wgpu::TextureFormat::Bgra8Unorm;
+    };
+
+    let surface_caps = surface.get_capabilities(&wgpu.adapter);
+    let alpha_mode = if surface_caps.alpha_modes.contains(&wgpu::CompositeAlphaMode::PreMultiplied)
+    {
+      wgpu::CompositeAlphaMode::PreMultiplied
+    } else {
+      wgpu::CompositeAlphaMode::Opaque
+    };
+    let surface_format =
+      surface_format(&surface_caps.formats, alpha_mode).expect("surface has no supported formats");
 
     let config = wgpu::SurfaceConfiguration {
       usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -136,58 +575,311 @@ impl<A: App> Gpu<A> {
       width,
       height,
       present_mode: wgpu::PresentMode::AutoVsync,
-      alpha_mode: if surface_caps.alpha_modes.contains(&wgpu::CompositeAlphaMode::PreMultiplied) {
-        wgpu::CompositeAlphaMode::PreMultiplied
-      } else {
-        wgpu::CompositeAlphaMode::Opaque
-      },
+      alpha_mode,
       view_formats: vec![],
       desired_maximum_frame_latency: 2,
     };
 
-    surface.configure(&self.device, &config);
+    surface.configure(&wgpu.device, &config);
+
+    self.bars.insert(id, bar(Some(Surface { surface, config, error: None })));
+    surface_format
+  }
+
+  /// Creates the bar `id` without a surface. It can only be drawn with
+  /// `draw_offscreen`, into textures of the given `format`, or into pixels if
+  /// bars are drawn on the CPU.
+  pub fn add_offscreen(
+    &mut self,
+    id: BarId,
+    output: &OutputInfo,
+    format: wgpu::TextureFormat,
+    scale: f64,
+    width: u32,
+    height: u32,
+  ) {
+    self.app.create_bar(id, output, device(&self.wgpu), format, scale, width, height);
+    self.app.set_size(id, device(&self.wgpu), scale, width, height);
+  }
+
+  /// Draws the bar `id`, which was created with `add_offscreen`, into
+  /// `target`.
+  pub fn draw_offscreen(&mut self, id: BarId, target: Target) {
+    self.app.draw(id, device(&self.wgpu), target);
+  }
 
-    self.bars.insert(id, Bar { surface, surface_config: config, scale });
-    self.app.create_bar(id, &self.device, surface_format, scale, width, height);
+  pub fn remove_offscreen(&mut self, id: BarId) { self.app.destroy_bar(id); }
+
+  pub fn take_popup(&mut self, id: BarId, popup: BarId) -> Option<PopupRequest> {
+    self.app.take_popup(id, popup)
+  }
+  pub fn popup_closed(&self, id: BarId) -> bool { self.app.popup_closed(id) }
+
+  /// Destroys the surface of the bar `id`. This must be called before the
+  /// underlying window is destroyed.
+  pub fn remove_surface(&mut self, id: BarId) {
+    // Popups can be closed before their surface is created, and the app still
+    // needs to forget about them.
+    if self.bars.remove(&id).is_some() || id.is_popup() {
+      self.app.destroy_bar(id);
+    }
   }
 
   pub fn move_mouse(&mut self, id: BarId, pos: Option<(f64, f64)>) {
     self.cursor = pos;
     self.app.move_mouse(id, pos);
   }
-  pub fn click_mouse(&mut self, id: BarId) {
+  pub fn click_mouse(&mut self, id: BarId, click: Click) {
+    if let Some(pos) = self.cursor {
+      self.app.click_mouse(id, pos, click);
+    }
+  }
+
+  pub fn scroll_mouse(&mut self, id: BarId, scroll: Scroll) {
     if let Some(pos) = self.cursor {
-      self.app.click_mouse(id, pos);
+      self.app.scroll_mouse(id, pos, scroll);
     }
   }
 
-  pub fn needs_render(&self) -> bool { self.bars.keys().any(|id| self.app.dirty(*id)) }
+  pub fn update(&mut self) -> bool { self.app.update() }
+
+  pub fn needs_render(&self) -> bool {
+    let now = Instant::now();
+    let dirty = |(id, bar): (&BarId, &Bar)| {
+      !bar.retrying(now) && (bar.force_draw || !bar.frame_pending && self.app.dirty(*id))
+    };
+    self.device_lost() || self.bars.iter().any(dirty)
+  }
+
+  fn device_lost(&self) -> bool { self.wgpu.as_ref().is_some_and(Wgpu::is_lost) }
+
+  /// Returns the earliest time any bar needs to be drawn again, or a timer
+  /// needs to run.
+  pub fn deadline(&self) -> Option<std::time::Instant> {
+    let timers = self.events.as_ref().and_then(|events| events.deadline());
+    // Bars waiting on a frame are woken up by the compositor instead.
+    let now = Instant::now();
+    let bars = self.bars.iter().filter(|(_, bar)| !bar.frame_pending);
+    let deadline = |(id, bar): (&BarId, &Bar)| match bar.retry_at {
+      Some(retry_at) if retry_at > now => Some(retry_at),
+      _ => self.app.deadline(*id),
+    };
+    bars.filter_map(deadline).chain(timers).min()
+  }
+
+  /// Renders every dirty bar that isn't waiting on a frame, and every bar
+  /// passed to `render_bar`. Each bar is passed to `present` once it's drawn,
+  /// and won't be drawn again until `frame_done` is called for it.
+  pub fn render(&mut self, present: &mut impl Present) {
+    if self.device_lost() {
+      self.recreate_device();
+    }
+
+    let device = device(&self.wgpu);
+    let now = Instant::now();
 
-  pub fn render(&mut self) {
     for (&id, bar) in &mut self.bars {
-      if self.app.dirty(id) {
-        let output = bar.surface.get_current_texture().unwrap();
+      if bar.retrying(now) || !bar.force_draw && (bar.frame_pending || !self.app.dirty(id)) {
+        continue;
+      }
 
-        self.app.draw(id, &self.device, &self.queue, &output.texture);
-        output.present();
+      match (&self.wgpu, &mut bar.surface) {
+        (Some(wgpu), Some(surface)) => {
+          // Skip the frame if the surface keeps timing out, or stays
+          // outdated after being configured again.
+          let Some(output) = surface.texture(&wgpu.device) else {
+            bar.retry_at = Some(now + RETRY_DELAY);
+            continue;
+          };
+
+          let damage = self.app.draw(id, device, Target::Texture(&output.texture));
+          present.present(id, &damage);
+          let suboptimal = output.suboptimal;
+          output.present();
+
+          if suboptimal {
+            surface.surface.configure(&wgpu.device, &surface.config);
+          }
+        }
+        _ => {
+          let Some(pixels) = present.buffer(id, bar.width, bar.height) else {
+            bar.retry_at = Some(now + RETRY_DELAY);
+            continue;
+          };
+
+          let damage = self.app.draw(id, device, Target::Pixels(pixels));
+          present.present(id, &damage);
+        }
       }
+
+      bar.frame_pending = true;
+      bar.force_draw = false;
+      bar.retry_at = None;
     }
   }
 
-  pub fn render_bar(&mut self, id: BarId) {
-    let output = self.bars.get(&id).unwrap().surface.get_current_texture().unwrap();
+  /// Creates the device again after it was lost, along with everything on it.
+  /// If there's no usable GPU anymore, bars are drawn on the CPU instead.
+  fn recreate_device(&mut self) {
+    let Some(old) = self.wgpu.take() else { return };
+
+    // Surfaces belong to the instance, so they're kept, and only configured
+    // again with the new device.
+    match Wgpu::with_instance(old.instance.clone(), false) {
+      Ok(wgpu) => {
+        for bar in self.bars.values_mut() {
+          if let Some(surface) = &mut bar.surface {
+            surface.surface.configure(&wgpu.device, &surface.config);
+            surface.error = None;
+          }
+        }
+        wgpu.watch_lost(self.waker.clone());
+        self.wgpu = Some(wgpu);
+      }
+      Err(e) => {
+        eprintln!("cannot recreate the GPU device, drawing on the CPU instead: {e}");
+        for bar in self.bars.values_mut() {
+          bar.surface = None;
+        }
+      }
+    }
+
+    if let Err(e) = self.app.set_device(device(&self.wgpu)) {
+      eprintln!("cannot draw with the recreated GPU device, drawing on the CPU instead: {e}");
+      self.wgpu = None;
+      for bar in self.bars.values_mut() {
+        bar.surface = None;
+      }
+      let _ = self.app.set_device(Device::Cpu);
+    }
+    for bar in self.bars.values_mut() {
+      bar.force_draw = true;
+    }
+  }
+
+  /// Called once the compositor wants a new frame for the bar `id`.
+  pub fn frame_done(&mut self, id: BarId) {
+    if let Some(bar) = self.bars.get_mut(&id) {
+      bar.frame_pending = false;
+    }
+  }
 
-    self.app.draw(id, &self.device, &self.queue, &output.texture);
-    output.present();
+  /// Draws the bar `id` on the next call to `render`, even if it isn't dirty,
+  /// or is waiting on a frame.
+  pub fn render_bar(&mut self, id: BarId) {
+    if let Some(bar) = self.bars.get_mut(&id) {
+      bar.force_draw = true;
+    }
   }
 
   pub fn set_size(&mut self, id: BarId, scale: f64, width: u32, height: u32) {
     let Some(bar) = self.bars.get_mut(&id) else { return };
 
-    bar.surface_config.width = (width as f64 * scale) as u32;
-    bar.surface_config.height = (height as f64 * scale) as u32;
+    bar.width = (width as f64 * scale) as u32;
+    bar.height = (height as f64 * scale) as u32;
+
+    let device = device(&self.wgpu);
+    if let (Some(wgpu), Some(surface)) = (&self.wgpu, &mut bar.surface) {
+      surface.config.width = bar.width;
+      surface.config.height = bar.height;
+      surface.surface.configure(&wgpu.device, &surface.config);
+    }
+    self.app.set_size(id, device, scale, width, height);
+  }
+}
+
+impl Wgpu {
+  fn new(force_fallback_adapter: bool) -> Result<Self, String> {
+    Self::with_instance(wgpu::Instance::new(&Default::default()), force_fallback_adapter)
+  }
+
+  fn with_instance(instance: wgpu::Instance, force_fallback_adapter: bool) -> Result<Self, String> {
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+      power_preference: wgpu::PowerPreference::default(),
+      compatible_surface: None,
+      force_fallback_adapter,
+    }))
+    .map_err(|e| e.to_string())?;
+    let (device, queue) =
+      pollster::block_on(adapter.request_device(&Default::default())).map_err(|e| e.to_string())?;
+
+    Ok(Wgpu { instance, adapter, device, queue, lost: Arc::new(AtomicBool::new(false)) })
+  }
+
+  /// Sets `lost` once the device is lost, and wakes up the main loop so that
+  /// it can be created again.
+  fn watch_lost(&self, waker: Option<Arc<Waker>>) {
+    let lost = self.lost.clone();
+    self.device.set_device_lost_callback(move |reason, message| {
+      // This is also called when the old device is dropped.
+      if reason == wgpu::DeviceLostReason::Destroyed {
+        return;
+      }
+
+      eprintln!("GPU device lost: {message}");
+      lost.store(true, Ordering::Release);
+      if let Some(waker) = &waker {
+        waker.wake();
+      }
+    });
+  }
+
+  fn is_lost(&self) -> bool { self.lost.load(Ordering::Acquire) }
+}
+
+impl Surface {
+  /// Returns the texture to draw the next frame into. Outdated and lost
+  /// surfaces are configured again, and the frame is skipped if that doesn't
+  /// help either.
+  fn texture(&mut self, device: &wgpu::Device) -> Option<wgpu::SurfaceTexture> {
+    let mut result = self.surface.get_current_texture();
+    if let Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) = result {
+      self.surface.configure(device, &self.config);
+      result = self.surface.get_current_texture();
+    }
+
+    match result {
+      Ok(texture) => {
+        self.error = None;
+        Some(texture)
+      }
+      Err(e) => {
+        if self.error.as_ref() != Some(&e) {
+          eprintln!("skipping frame: {e}");
+          self.error = Some(e);
+        }
+        None
+      }
+    }
+  }
+}
+
+/// Picks the format with the most precision out of `formats`, so that
+/// gradients don't band. The blitter handles both sRGB and linear formats.
+fn surface_format(
+  formats: &[wgpu::TextureFormat],
+  alpha_mode: wgpu::CompositeAlphaMode,
+) -> Option<wgpu::TextureFormat> {
+  use wgpu::TextureFormat::*;
+
+  // `Rgb10a2Unorm` only has 2 bits of alpha, which isn't enough to blend with
+  // the windows behind the bar.
+  let precise: &[_] = match alpha_mode {
+    wgpu::CompositeAlphaMode::Opaque => &[Rgb10a2Unorm, Rgba16Float],
+    _ => &[Rgba16Float],
+  };
+
+  precise
+    .iter()
+    .find(|f| formats.contains(f))
+    .or_else(|| formats.iter().find(|f| !f.is_srgb()))
+    .or(formats.first())
+    .copied()
+}
 
-    bar.surface.configure(&self.device, &bar.surface_config);
-    self.app.set_size(id, &self.device, scale, width, height);
+fn device(wgpu: &Option<Wgpu>) -> Device<'_> {
+  match wgpu {
+    Some(wgpu) => Device::Gpu(&wgpu.device, &wgpu.queue),
+    None => Device::Cpu,
   }
 }
@@ -19,6 +19,11 @@ fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
   return out;
 }
 
+// Set for sRGB and float targets, which encode the output themselves.
+override LINEAR_OUTPUT: bool = false;
+// The size of one step of the target in sRGB, or 0 to not dither.
+override DITHER: f32 = 0.0;
+
 @group(0) @binding(0)
 var texture: texture_2d<f32>;
 @group(0) @binding(1)
@@ -27,19 +32,35 @@ var texture_sampler: sampler;
 @fragment
 fn fs_main(vs: VertexOutput) -> @location(0) vec4<f32> {
   let color = textureSample(texture, texture_sampler, vs.tex_coords);
-  let rgb = oklab_to_linear_rgb(vec3(
+  // Float targets aren't clamped, so colors outside of sRGB are clamped here.
+  let rgb = saturate(oklab_to_linear_rgb(vec3(
     color.r,
     color.g - 0.5,
     color.b - 0.5,
-  ));
-  let srgb = vec3<f32>(
-    linear_to_srgb(rgb.r),
-    linear_to_srgb(rgb.g),
-    linear_to_srgb(rgb.b),
-  );
+  )));
+  if DITHER == 0.0 {
+    if LINEAR_OUTPUT {
+      return vec4<f32>(rgb, color.a);
+    }
+    return vec4<f32>(linear_to_srgb3(rgb), color.a);
+  }
+
+  // Dither in sRGB, where the steps of the target are, even if the GPU encodes
+  // the output itself.
+  let noise = interleaved_gradient_noise(vs.position.xy) - 0.5;
+  let srgb = saturate(linear_to_srgb3(rgb) + noise * DITHER);
+  if LINEAR_OUTPUT {
+    return vec4<f32>(srgb_to_linear3(srgb), color.a);
+  }
   return vec4<f32>(srgb, color.a);
 }
 
+// Noise between 0 and 1 that doesn't repeat in small patterns, from "Next
+// Generation Post Processing in Call of Duty: Advanced Warfare".
+fn interleaved_gradient_noise(p: vec2<f32>) -> f32 {
+  return fract(52.9829189 * fract(dot(p, vec2(0.06711056, 0.00583715))));
+}
+
 fn oklab_to_linear_rgb(c: vec3<f32>) -> vec3<f32> {
   let l_ = c.r + 0.3963377774f * c.g + 0.2158037573f * c.b;
   let m_ = c.r - 0.1055613458f * c.g - 0.0638541728f * c.b;
@@ -56,6 +77,14 @@ fn oklab_to_linear_rgb(c: vec3<f32>) -> vec3<f32> {
   );
 }
 
+fn linear_to_srgb3(c: vec3<f32>) -> vec3<f32> {
+  return vec3(linear_to_srgb(c.r), linear_to_srgb(c.g), linear_to_srgb(c.b));
+}
+
+fn srgb_to_linear3(c: vec3<f32>) -> vec3<f32> {
+  return vec3(srgb_to_linear(c.r), srgb_to_linear(c.g), srgb_to_linear(c.b));
+}
+
 fn linear_to_srgb(x: f32) -> f32 {
   if x >= 0.0031308 {
     return 1.055 * pow(x, 1.0 / 2.4) - 0.055;
@@ -63,3 +92,11 @@ fn linear_to_srgb(x: f32) -> f32 {
     return 12.92 * x;
   }
 }
+
+fn srgb_to_linear(x: f32) -> f32 {
+  if x >= 0.04045 {
+    return pow((x + 0.055) / 1.055, 2.4);
+  } else {
+    return x / 12.92;
+  }
+}
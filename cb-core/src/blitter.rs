@@ -2,6 +2,13 @@ use wgpu::*;
 
 /// Similar to `wgpu::util::TextureBlitter`, but transforms a texture in OkLAB
 /// to sRGB.
+///
+/// The target may be in any format. For sRGB and float formats, the GPU or the
+/// compositor encodes the output, so the shader writes linear colors instead.
+/// Output to formats with integer steps is dithered, which hides some of the
+/// banding from the 8-bit source texture.
+///
+/// Only the given rects are written, and they replace what the target had.
 pub struct TextureBlitterConvert {
   pipeline:          RenderPipeline,
   bind_group_layout: BindGroupLayout,
@@ -48,6 +55,10 @@ impl TextureBlitterConvert {
     });
 
     let shader = device.create_shader_module(include_wgsl!("blit.wgsl"));
+    let constants = [
+      ("LINEAR_OUTPUT", if linear_output(format) { 1.0 } else { 0.0 }),
+      ("DITHER", dither(format)),
+    ];
     let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
       label:         Some("TextureBlitterConvert::pipeline"),
       layout:        Some(&pipeline_layout),
@@ -71,10 +82,22 @@ impl TextureBlitterConvert {
       fragment:      Some(FragmentState {
         module:              &shader,
         entry_point:         Some("fs_main"),
-        compilation_options: PipelineCompilationOptions::default(),
+        compilation_options: PipelineCompilationOptions {
+          constants: &constants,
+          ..Default::default()
+        },
         targets:             &[Some(ColorTargetState {
-          format:     format,
-          blend:      Some(BlendState::ALPHA_BLENDING),
+          format,
+          // Premultiplies the straight colors from the shader, and replaces
+          // what the target had.
+          blend: Some(BlendState {
+            color: BlendComponent {
+              src_factor: BlendFactor::SrcAlpha,
+              dst_factor: BlendFactor::Zero,
+              operation:  BlendOperation::Add,
+            },
+            alpha: BlendComponent::REPLACE,
+          }),
           write_mask: ColorWrites::ALL,
         })],
       }),
@@ -91,6 +114,7 @@ impl TextureBlitterConvert {
     encoder: &mut CommandEncoder,
     source: &TextureView,
     target: &TextureView,
+    rects: &[kurbo::Rect],
   ) {
     let bind_group = device.create_bind_group(&BindGroupDescriptor {
       label:   Some("TextureBlitterConvert::bind_group"),
@@ -115,6 +139,26 @@ impl TextureBlitterConvert {
     });
     pass.set_pipeline(&self.pipeline);
     pass.set_bind_group(0, &bind_group, &[]);
-    pass.draw(0..3, 0..1);
+    for rect in rects {
+      let (x, y) = (rect.x0 as u32, rect.y0 as u32);
+      pass.set_scissor_rect(x, y, rect.x1 as u32 - x, rect.y1 as u32 - y);
+      pass.draw(0..3, 0..1);
+    }
+  }
+}
+
+/// Returns whether `format` expects linear colors. wgpu configures float
+/// surfaces as extended linear sRGB.
+fn linear_output(format: TextureFormat) -> bool {
+  format.is_srgb() || matches!(format, TextureFormat::Rgba16Float | TextureFormat::Rgba32Float)
+}
+
+/// Returns the size of one step of `format` in sRGB, or 0 for float formats,
+/// which don't need dithering.
+fn dither(format: TextureFormat) -> f64 {
+  match format {
+    TextureFormat::Rgba16Float | TextureFormat::Rgba32Float => 0.0,
+    TextureFormat::Rgb10a2Unorm => 1.0 / 1023.0,
+    _ => 1.0 / 255.0,
   }
 }
@@ -0,0 +1,138 @@
+//! Draws bars on the CPU, for machines without a usable GPU.
+
+use kurbo::{Affine, BezPath, Stroke};
+use peniko::color::{AlphaColor, Oklab, Srgb};
+use vello_cpu::{Pixmap, RenderContext};
+
+/// Drawing commands, which are replayed into a `vello_cpu` context once the
+/// frame is done. `vello_cpu` can't append one recording to another, so this
+/// is used instead for fragments.
+#[derive(Clone, Default)]
+pub(crate) struct CpuScene {
+  commands: Vec<Command>,
+}
+
+#[derive(Clone)]
+enum Command {
+  Fill { transform: Affine, brush: peniko::Brush, path: BezPath },
+  Stroke { transform: Affine, brush: peniko::Brush, stroke: Stroke, path: BezPath },
+  Glyphs(GlyphRun),
+}
+
+#[derive(Clone)]
+pub(crate) struct GlyphRun {
+  pub font:            peniko::FontData,
+  pub font_size:       f32,
+  pub coords:          Vec<i16>,
+  pub transform:       Affine,
+  pub glyph_transform: Option<Affine>,
+  pub brush:           peniko::Brush,
+  pub glyphs:          Vec<vello::Glyph>,
+}
+
+/// The pixels of a bar drawn on the CPU.
+pub(crate) struct CpuTarget {
+  context: RenderContext,
+  pixmap:  Pixmap,
+}
+
+impl CpuScene {
+  pub fn fill(&mut self, transform: Affine, brush: &peniko::Brush, shape: &impl kurbo::Shape) {
+    let path = shape.to_path(0.1);
+    self.commands.push(Command::Fill { transform, brush: brush.clone(), path });
+  }
+
+  pub fn stroke(
+    &mut self,
+    stroke: &Stroke,
+    transform: Affine,
+    brush: &peniko::Brush,
+    shape: &impl kurbo::Shape,
+  ) {
+    let path = shape.to_path(0.1);
+    self.commands.push(Command::Stroke {
+      transform,
+      brush: brush.clone(),
+      stroke: stroke.clone(),
+      path,
+    });
+  }
+
+  pub fn draw_glyphs(&mut self, run: GlyphRun) { self.commands.push(Command::Glyphs(run)); }
+
+  pub fn append(&mut self, other: &CpuScene) { self.commands.extend_from_slice(&other.commands); }
+}
+
+impl CpuTarget {
+  pub fn new(width: u32, height: u32) -> Self {
+    let (width, height) = (width.min(u16::MAX.into()) as u16, height.min(u16::MAX.into()) as u16);
+    CpuTarget { context: RenderContext::new(width, height), pixmap: Pixmap::new(width, height) }
+  }
+
+  pub fn size(&self) -> (u32, u32) { (self.pixmap.width().into(), self.pixmap.height().into()) }
+
+  /// Draws `scene` into `out`, which is in the layout of `Target::Pixels`.
+  pub fn render(&mut self, scene: &CpuScene, out: &mut [u8]) {
+    let ctx = &mut self.context;
+    ctx.reset();
+
+    for command in &scene.commands {
+      match command {
+        Command::Fill { transform, brush, path } => {
+          ctx.set_transform(*transform);
+          ctx.set_paint(paint(brush));
+          ctx.fill_path(path);
+        }
+        Command::Stroke { transform, brush, stroke, path } => {
+          ctx.set_transform(*transform);
+          ctx.set_paint(paint(brush));
+          ctx.set_stroke(stroke.clone());
+          ctx.stroke_path(path);
+        }
+        Command::Glyphs(run) => {
+          ctx.set_transform(run.transform);
+          ctx.set_paint(paint(&run.brush));
+          let mut builder = ctx
+            .glyph_run(&run.font)
+            .font_size(run.font_size)
+            .hint(true)
+            .normalized_coords(&run.coords);
+          if let Some(transform) = run.glyph_transform {
+            builder = builder.glyph_transform(transform);
+          }
+          builder.fill_glyphs(run.glyphs.iter().map(|glyph| vello_cpu::Glyph {
+            id: glyph.id,
+            x:  glyph.x,
+            y:  glyph.y,
+          }));
+        }
+      }
+    }
+
+    ctx.flush();
+    ctx.render_to_pixmap(&mut self.pixmap);
+
+    // Just like the blitter, this undoes `encode_color`, and converts to sRGB.
+    for (src, dst) in self.pixmap.data_as_u8_slice().chunks_exact(4).zip(out.chunks_exact_mut(4)) {
+      let alpha = src[3];
+      if alpha == 0 {
+        dst.copy_from_slice(&[0; 4]);
+        continue;
+      }
+
+      let [l, a, b] = [src[0], src[1], src[2]].map(|c| f32::from(c) / f32::from(alpha));
+      let color = AlphaColor::<Oklab>::new([l, a - 0.5, b - 0.5, f32::from(alpha) / 255.0]);
+      let [r, g, b, _] = color.convert::<Srgb>().premultiply().to_rgba8().to_u8_array();
+      dst.copy_from_slice(&[b, g, r, alpha]);
+    }
+  }
+}
+
+fn paint(brush: &peniko::Brush) -> vello_cpu::PaintType {
+  match brush {
+    peniko::Brush::Solid(color) => (*color).into(),
+    peniko::Brush::Gradient(gradient) => gradient.clone().into(),
+    // Images are never drawn.
+    peniko::Brush::Image(_) => peniko::color::palette::css::TRANSPARENT.into(),
+  }
+}
@@ -1,6 +1,6 @@
 use std::{borrow::Cow, collections::HashMap, fmt, ops::Range};
 
-use kurbo::{Affine, Point, Rect, Stroke, Vec2};
+use kurbo::{Affine, BezPath, Point, Rect, Shape, Stroke, Vec2};
 use parley::{FontContext, LayoutContext};
 use peniko::{
   Fill, Gradient,
@@ -8,12 +8,20 @@ use peniko::{
 };
 use vello::{RenderParams, Scene};
 
-use crate::{blitter::TextureBlitterConvert, quad::Quad};
+use crate::{
+  blitter::TextureBlitterConvert,
+  cpu::{CpuScene, CpuTarget, GlyphRun},
+  quad::Quad,
+};
 
-pub use cb_common::{App, BarId, Waker};
+pub use cb_common::{
+  App, BarId, Click, Damage, Device, Edge, EventLoop, Exclusive, Geometry, Layer, Margin,
+  Modifiers, MouseButton, OutputInfo, PopupKind, PopupRequest, PostAction, Scroll, Target, Waker,
+};
 pub use wgpu;
 
 mod blitter;
+mod cpu;
 mod quad;
 
 pub type Color = AlphaColor<Oklab>;
@@ -26,118 +34,312 @@ pub struct RenderStore {
   font:   FontContext,
   layout: LayoutContext<peniko::Brush>,
 
-  render: vello::Renderer,
+  /// Shaped text, so that text that didn't change isn't shaped again. Used
+  /// entries are moved from `old_text` to `text`, and `old_text` is dropped
+  /// once `text` fills up.
+  text:     HashMap<TextKey, parley::Layout<peniko::Brush>>,
+  old_text: HashMap<TextKey, parley::Layout<peniko::Brush>>,
+
+  /// The GPU renderer, or `None` if bars are drawn on the CPU.
+  render:       Option<vello::Renderer>,
+  /// Set after the renderer fails, so that the error is only logged once.
+  render_error: bool,
 
   bars: HashMap<BarId, Bar>,
+
+  /// If set, every frame is drawn as if it was this time.
+  frame_time: Option<std::time::Instant>,
+}
+
+/// How many shaped layouts to keep before dropping the old ones.
+const TEXT_CACHE_SIZE: usize = 256;
+
+/// The size of text, in logical pixels.
+const FONT_SIZE: f32 = 12.0;
+
+/// Everything that changes how a `Text` is shaped. Text is always shaped with
+/// the default font stack and weight, at `FONT_SIZE` times the scale, so the
+/// font settings only need to be part of the key once they can change.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TextKey {
+  text:   String,
+  ranges: Vec<(Range<usize>, [u32; 4])>,
+  color:  [u32; 4],
+  scale:  u64,
 }
 
 struct Bar {
-  scale:        f64,
+  scale:  f64,
+  target: BarTarget,
+  /// The format of the output the bar is copied to.
+  format: wgpu::TextureFormat,
+
+  cursor: Option<Point>,
+}
+
+/// What a bar is drawn into before it's copied to the output.
+enum BarTarget {
+  Gpu(Box<GpuTarget>),
+  Cpu(Box<CpuTarget>),
+}
+
+struct GpuTarget {
   blitter:      TextureBlitterConvert,
+  copier:       wgpu::util::TextureBlitter,
+  /// vello draws the whole bar into this, in OkLAB.
   texture:      wgpu::Texture,
   texture_view: wgpu::TextureView,
+  /// The bar in the format of the output. Only the damaged parts are
+  /// converted into this, the rest is kept from earlier frames.
+  output:       wgpu::Texture,
+  output_view:  wgpu::TextureView,
+}
 
-  cursor: Option<Point>,
+/// The drawing commands of a frame, for the renderer the bar is drawn with.
+#[derive(Clone)]
+enum Canvas {
+  Gpu(Box<Scene>),
+  Cpu(CpuScene),
 }
 
 pub struct Render<'a> {
-  bar:    BarId,
-  scale:  f64,
-  cursor: Option<Point>,
+  bar:            BarId,
+  scale:          f64,
+  cursor:         Option<Point>,
+  /// Set once something drawn depends on where the cursor is.
+  follows_cursor: bool,
 
   frame_time: std::time::Instant,
   offset:     Vec2,
 
-  store: &'a mut RenderStore,
-  scene: Scene,
+  store:  &'a mut RenderStore,
+  canvas: Canvas,
 }
 
 impl RenderStore {
-  pub fn new(device: &wgpu::Device) -> Self {
+  pub fn new(device: Device) -> Self {
     RenderStore {
-      font:   FontContext::new(),
-      layout: LayoutContext::new(),
-      render: vello::Renderer::new(device, Default::default()).unwrap(),
-      bars:   HashMap::new(),
+      font:         FontContext::new(),
+      layout:       LayoutContext::new(),
+      text:         HashMap::new(),
+      old_text:     HashMap::new(),
+      render:       renderer(device).unwrap_or_else(|e| {
+        eprintln!("cannot render bars: {e}");
+        None
+      }),
+      render_error: false,
+      bars:         HashMap::new(),
+      frame_time:   None,
     }
   }
 
+  /// Replaces the device after the old one was lost, and creates the renderer
+  /// and the textures of every bar again. Fails if there's no renderer for the
+  /// new device, in which case it should be replaced with `Device::Cpu`.
+  pub fn set_device(&mut self, device: Device) -> Result<(), String> {
+    self.render = renderer(device)?;
+    self.render_error = false;
+
+    for bar in self.bars.values_mut() {
+      let (width, height) = match &bar.target {
+        BarTarget::Gpu(target) => (target.texture.width(), target.texture.height()),
+        BarTarget::Cpu(target) => target.size(),
+      };
+      bar.target = BarTarget::new(device, bar.format, width, height);
+    }
+
+    Ok(())
+  }
+
+  /// Draws every frame as if it was `time`, instead of the current time. This
+  /// keeps animations still, for snapshots.
+  pub fn set_frame_time(&mut self, time: Option<std::time::Instant>) { self.frame_time = time; }
+
+  /// Draws all text with the font in `data`, instead of the fonts installed on
+  /// the system. Text isn't drawn with any other font, not even for characters
+  /// missing from this one, so snapshots look the same on every machine.
+  pub fn set_font(&mut self, data: Vec<u8>) {
+    use parley::fontique::{Collection, CollectionOptions, GenericFamily};
+
+    let mut collection =
+      Collection::new(CollectionOptions { shared: false, system_fonts: false });
+    let families = collection.register_fonts(data.into(), None);
+    let families = families.iter().map(|(family, _)| *family).collect::<Vec<_>>();
+    collection.set_generic_families(GenericFamily::SansSerif, families.into_iter());
+
+    self.font = FontContext { collection, source_cache: Default::default() };
+    self.text.clear();
+    self.old_text.clear();
+  }
+
   pub fn create_bar(
     &mut self,
     id: BarId,
-    device: &wgpu::Device,
+    device: Device,
     surface_format: wgpu::TextureFormat,
     scale: f64,
     width: u32,
     height: u32,
   ) {
-    let format = wgpu::TextureFormat::Rgba8Unorm;
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-      label: None,
-      size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
-      mip_level_count: 1,
-      sample_count: 1,
-      dimension: wgpu::TextureDimension::D2,
-      usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-      format,
-      view_formats: &[],
-    });
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-    let blitter = TextureBlitterConvert::new(&device, surface_format);
-
-    self.bars.insert(id, Bar { scale, texture, texture_view, blitter, cursor: None });
+    let target = BarTarget::new(device, surface_format, width, height);
+    self.bars.insert(id, Bar { scale, target, format: surface_format, cursor: None });
+  }
+
+  /// Frees the textures of the bar `id`.
+  pub fn destroy_bar(&mut self, id: BarId) {
+    if let Some(Bar { target: BarTarget::Gpu(target), .. }) = self.bars.remove(&id) {
+      target.texture.destroy();
+      target.output.destroy();
+    }
   }
 
   pub fn for_bar(&mut self, id: BarId) -> Option<Render<'_>> {
     if let Some(bar) = self.bars.get(&id) {
+      let canvas = match bar.target {
+        BarTarget::Gpu(_) => Canvas::Gpu(Box::default()),
+        BarTarget::Cpu(_) => Canvas::Cpu(CpuScene::default()),
+      };
+
       Some(Render {
-        bar:        id,
-        frame_time: std::time::Instant::now(),
-        scale:      bar.scale.into(),
-        cursor:     bar.cursor,
-        offset:     Vec2::ZERO,
-        store:      self,
-        scene:      Scene::new(),
+        bar: id,
+        frame_time: self.frame_time.unwrap_or_else(std::time::Instant::now),
+        scale: bar.scale,
+        cursor: bar.cursor,
+        follows_cursor: false,
+        offset: Vec2::ZERO,
+        store: self,
+        canvas,
       })
     } else {
       None
     }
   }
 
+  /// Shapes `text` and breaks it into lines. This reuses the layout from an
+  /// earlier call with the same text, colors and scale.
+  pub fn layout_text(
+    &mut self,
+    text: Text,
+    color: Color,
+    scale: f64,
+  ) -> parley::Layout<peniko::Brush> {
+    let key = TextKey::new(&text, color, scale);
+    if let Some(layout) = self.text.get(&key) {
+      return layout.clone();
+    }
+
+    let layout = match self.old_text.remove(&key) {
+      Some(layout) => layout,
+      None => {
+        let mut layout = text.layout(self, encode_color(color).into(), scale);
+        layout.break_all_lines(None);
+        layout.align(None, parley::Alignment::Start, parley::AlignmentOptions::default());
+        layout
+      }
+    };
+
+    if self.text.len() >= TEXT_CACHE_SIZE {
+      self.old_text = std::mem::take(&mut self.text);
+    }
+    self.text.insert(key, layout.clone());
+    layout
+  }
+
   pub fn move_mouse(&mut self, id: BarId, pos: Option<(f64, f64)>) {
     self.bars.get_mut(&id).unwrap().cursor = pos.map(|(x, y)| Point::new(x as f64, y as f64));
   }
 
-  pub fn set_size(
-    &mut self,
-    id: BarId,
-    device: &wgpu::Device,
-    scale: f64,
-    width: u32,
-    height: u32,
-  ) {
+  pub fn set_size(&mut self, id: BarId, device: Device, scale: f64, width: u32, height: u32) {
     let bar = self.bars.get_mut(&id).unwrap();
     bar.scale = scale;
 
-    bar.texture = device.create_texture(&wgpu::TextureDescriptor {
-      label:           None,
-      size:            wgpu::Extent3d {
-        width:                 (width as f64 * scale) as u32,
-        height:                (height as f64 * scale) as u32,
-        depth_or_array_layers: 1,
-      },
-      mip_level_count: 1,
-      sample_count:    1,
-      dimension:       wgpu::TextureDimension::D2,
-      usage:           wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-      format:          bar.texture.format(),
-      view_formats:    &[],
-    });
-    bar.texture_view = bar.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let (width, height) = ((width as f64 * scale) as u32, (height as f64 * scale) as u32);
+    let (BarTarget::Gpu(target), Device::Gpu(device, _)) = (&mut bar.target, device) else {
+      bar.target = BarTarget::Cpu(Box::new(CpuTarget::new(width, height)));
+      return;
+    };
+
+    target.texture.destroy();
+    target.output.destroy();
+    (target.texture, target.texture_view) = bar_texture(device, width, height);
+    (target.output, target.output_view) = output_texture(device, bar.format, width, height);
+  }
+}
+
+fn renderer(device: Device) -> Result<Option<vello::Renderer>, String> {
+  match device {
+    Device::Gpu(device, _) => match vello::Renderer::new(device, Default::default()) {
+      Ok(renderer) => Ok(Some(renderer)),
+      Err(e) => Err(e.to_string()),
+    },
+    Device::Cpu => Ok(None),
+  }
+}
+
+impl BarTarget {
+  /// Creates the target for a bar of the given size, which is copied to an
+  /// output in `format`.
+  fn new(device: Device, format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+    let Device::Gpu(device, _) = device else {
+      return BarTarget::Cpu(Box::new(CpuTarget::new(width, height)));
+    };
+
+    let (texture, texture_view) = bar_texture(device, width, height);
+    let (output, output_view) = output_texture(device, format, width, height);
+    let blitter = TextureBlitterConvert::new(device, format);
+    let copier = wgpu::util::TextureBlitter::new(device, format);
+    BarTarget::Gpu(Box::new(GpuTarget {
+      blitter,
+      copier,
+      texture,
+      texture_view,
+      output,
+      output_view,
+    }))
   }
 }
 
+/// Creates the texture that vello draws a bar into.
+fn bar_texture(
+  device: &wgpu::Device,
+  width: u32,
+  height: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+  let texture = device.create_texture(&wgpu::TextureDescriptor {
+    label:           None,
+    size:            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count:    1,
+    dimension:       wgpu::TextureDimension::D2,
+    usage:           wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+    format:          wgpu::TextureFormat::Rgba8Unorm,
+    view_formats:    &[],
+  });
+  let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+  (texture, texture_view)
+}
+
+/// Creates the texture that keeps a bar in the format of its output.
+fn output_texture(
+  device: &wgpu::Device,
+  format: wgpu::TextureFormat,
+  width: u32,
+  height: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+  let texture = device.create_texture(&wgpu::TextureDescriptor {
+    label: None,
+    size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: wgpu::TextureDimension::D2,
+    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    format,
+    view_formats: &[],
+  });
+  let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+  (texture, texture_view)
+}
+
 /// Converts things to sRGB, so that vello uses OkLAB for everything, and then
 /// we undo this conversion in the blitter.
 pub fn encode_color(color: Color) -> AlphaColor<Srgb> {
@@ -146,7 +348,7 @@ pub fn encode_color(color: Color) -> AlphaColor<Srgb> {
   AlphaColor::new([l, a + 0.5, b + 0.5, alpha])
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Text<'a> {
   text:   Cow<'a, str>,
   ranges: Vec<(Range<usize>, Color)>,
@@ -163,6 +365,8 @@ impl<'a> From<&'a String> for Text<'a> {
 impl Text<'_> {
   pub fn new() -> Self { Text::default() }
 
+  pub fn is_empty(&self) -> bool { self.text.is_empty() }
+
   pub fn push(&mut self, text: impl fmt::Display, color: Color) {
     let start = self.text.len();
     std::fmt::write(self.text.to_mut(), format_args!("{text}")).unwrap();
@@ -178,7 +382,7 @@ impl Text<'_> {
   ) -> parley::Layout<peniko::Brush> {
     let mut builder = store.layout.ranged_builder(&mut store.font, &self.text, 1.0, false);
     builder.push_default(parley::StyleProperty::Brush(brush));
-    builder.push_default(parley::StyleProperty::FontSize(12.0 * scale as f32));
+    builder.push_default(parley::StyleProperty::FontSize(FONT_SIZE * scale as f32));
 
     for range in self.ranges {
       builder.push(parley::StyleProperty::Brush(encode_color(range.1).into()), range.0);
@@ -188,6 +392,88 @@ impl Text<'_> {
   }
 }
 
+/// Drawing commands recorded with `Render::record`, which can be replayed in
+/// later frames without running the drawing code again.
+#[derive(Clone)]
+pub struct Fragment {
+  canvas:         Canvas,
+  follows_cursor: bool,
+}
+
+impl Fragment {
+  /// Returns true if this fragment looks different depending on where the
+  /// cursor is, like buttons, so it has to be recorded again when the cursor
+  /// moves.
+  pub fn follows_cursor(&self) -> bool { self.follows_cursor }
+}
+
+impl Canvas {
+  /// Returns an empty canvas for the same renderer as this one.
+  fn empty(&self) -> Self {
+    match self {
+      Canvas::Gpu(_) => Canvas::Gpu(Box::default()),
+      Canvas::Cpu(_) => Canvas::Cpu(CpuScene::default()),
+    }
+  }
+
+  fn fill(&mut self, transform: Affine, brush: &peniko::Brush, shape: &impl kurbo::Shape) {
+    match self {
+      Canvas::Gpu(scene) => scene.fill(Fill::NonZero, transform, brush, None, shape),
+      Canvas::Cpu(scene) => scene.fill(transform, brush, shape),
+    }
+  }
+
+  fn stroke(
+    &mut self,
+    stroke: &Stroke,
+    transform: Affine,
+    brush: &peniko::Brush,
+    shape: &impl kurbo::Shape,
+  ) {
+    match self {
+      Canvas::Gpu(scene) => scene.stroke(stroke, transform, brush, None, shape),
+      Canvas::Cpu(scene) => scene.stroke(stroke, transform, brush, shape),
+    }
+  }
+
+  fn draw_glyphs(&mut self, run: GlyphRun) {
+    match self {
+      Canvas::Gpu(scene) => scene
+        .draw_glyphs(&run.font)
+        .brush(&run.brush)
+        .hint(true)
+        .transform(run.transform)
+        .glyph_transform(run.glyph_transform)
+        .font_size(run.font_size)
+        .normalized_coords(&run.coords)
+        .draw(Fill::NonZero, run.glyphs.into_iter()),
+      Canvas::Cpu(scene) => scene.draw_glyphs(run),
+    }
+  }
+
+  fn append(&mut self, other: &Canvas) {
+    match (self, other) {
+      (Canvas::Gpu(scene), Canvas::Gpu(other)) => scene.append(other, None),
+      (Canvas::Cpu(scene), Canvas::Cpu(other)) => scene.append(other),
+      // Fragments are only replayed into the bar they were recorded for.
+      _ => {}
+    }
+  }
+}
+
+impl TextKey {
+  fn new(text: &Text, color: Color, scale: f64) -> Self {
+    let bits = |color: Color| color.components.map(f32::to_bits);
+
+    TextKey {
+      text:   text.text.to_string(),
+      ranges: text.ranges.iter().map(|(range, color)| (range.clone(), bits(*color))).collect(),
+      color:  bits(color),
+      scale:  scale.to_bits(),
+    }
+  }
+}
+
 pub trait Drawable {
   fn draw(&self, ctx: &mut Render);
 }
@@ -230,13 +516,39 @@ impl Render<'_> {
     Affine::scale(self.scale.into()) * Affine::translate(self.offset)
   }
 
+  pub fn fill(&mut self, shape: &impl kurbo::Shape, brush: impl Into<Brush>) {
+    self.canvas.fill(self.transform(), &brush.into().encode(), shape);
+  }
+
   pub fn stroke(&mut self, shape: &impl kurbo::Shape, brush: impl Into<Brush>) {
-    self.scene.stroke(&Stroke::new(2.0), self.transform(), &brush.into().encode(), None, &shape);
+    self.canvas.stroke(&Stroke::new(2.0), self.transform(), &brush.into().encode(), shape);
   }
 
   pub fn draw(&mut self, drawable: &impl Drawable) { drawable.draw(self); }
 
+  /// Records everything `draw` renders into a fragment, instead of this frame.
+  pub fn record(&mut self, draw: impl FnOnce(&mut Self)) -> Fragment {
+    let empty = self.canvas.empty();
+    let frame = std::mem::replace(&mut self.canvas, empty);
+    let follows_cursor = std::mem::take(&mut self.follows_cursor);
+    draw(self);
+    Fragment {
+      canvas:         std::mem::replace(&mut self.canvas, frame),
+      follows_cursor: std::mem::replace(&mut self.follows_cursor, follows_cursor),
+    }
+  }
+
+  /// Adds a recorded fragment to this frame.
+  pub fn replay(&mut self, fragment: &Fragment) {
+    self.canvas.append(&fragment.canvas);
+    self.follows_cursor |= fragment.follows_cursor;
+  }
+
+  /// Draws the outline of a button, which tilts towards the cursor when it's
+  /// on or near the button.
   pub fn draw_button(&mut self, rect: &kurbo::Rect, color: Color) {
+    self.follows_cursor = true;
+
     let rect = *rect + self.offset;
     let mut quad = Quad::from(rect);
 
@@ -264,20 +576,11 @@ impl Render<'_> {
       color.into()
     };
 
-    self.scene.stroke(
-      &Stroke::new(2.0),
-      kurbo::Affine::scale(self.scale.into()),
-      &brush.encode(),
-      None,
-      &quad,
-    );
+    self.canvas.stroke(&Stroke::new(2.0), kurbo::Affine::scale(self.scale), &brush.encode(), &quad);
   }
 
   pub fn draw_text<'a>(&mut self, origin: Point, text: impl Into<Text<'a>>, color: Color) -> Rect {
-    let mut layout = text.into().layout(&mut self.store, encode_color(color).into(), self.scale);
-
-    layout.break_all_lines(None);
-    layout.align(None, parley::Alignment::Start, parley::AlignmentOptions::default());
+    let layout = self.store.layout_text(text.into(), color, self.scale);
 
     self.draw_text_layout(origin, &layout, None)
   }
@@ -301,61 +604,126 @@ impl Render<'_> {
         let mut x = rect.x0 as f32 + glyph_run.offset();
         let baseline = (rect.y0 as f32 + glyph_run.baseline()).round();
 
-        self
-          .scene
-          .draw_glyphs(run.font())
-          .brush(brush_override.as_ref().unwrap_or(&glyph_run.style().brush))
-          .hint(true)
-          .transform(Affine::translate((origin.to_vec2() + self.offset) * self.scale))
-          .glyph_transform(
-            run.synthesis().skew().map(|angle| Affine::skew(angle.to_radians().tan() as f64, 0.0)),
-          )
-          .font_size(run.font_size())
-          .normalized_coords(run.normalized_coords())
-          .draw(
-            Fill::NonZero,
-            glyph_run.glyphs().map(|glyph| {
+        self.canvas.draw_glyphs(GlyphRun {
+          font:            run.font().clone(),
+          font_size:       run.font_size(),
+          coords:          run.normalized_coords().to_vec(),
+          transform:       Affine::translate((origin.to_vec2() + self.offset) * self.scale),
+          glyph_transform: run
+            .synthesis()
+            .skew()
+            .map(|angle| Affine::skew(angle.to_radians().tan() as f64, 0.0)),
+          brush:           brush_override.as_ref().unwrap_or(&glyph_run.style().brush).clone(),
+          glyphs:          glyph_run
+            .glyphs()
+            .map(|glyph| {
               let gx = x + glyph.x;
               let gy = baseline + glyph.y;
               x += glyph.advance;
               vello::Glyph { id: glyph.id.into(), x: gx, y: gy }
-            }),
-          );
+            })
+            .collect(),
+        });
       }
     }
 
     rect.scale_from_origin(1.0 / self.scale) + origin.to_vec2()
   }
 
-  pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, surface: &wgpu::Texture) {
-    let bar = &self.store.bars[&self.bar];
-
-    self
-      .store
-      .render
-      .render_to_texture(
-        device,
-        queue,
-        &self.scene,
-        &bar.texture_view,
-        &RenderParams {
-          base_color:          encode_color(Color::BLACK.with_alpha(0.0)),
-          width:               bar.texture.width(),
-          height:              bar.texture.height(),
-          antialiasing_method: vello::AaConfig::Msaa16,
-        },
-      )
-      .unwrap();
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-    bar.blitter.copy(
-      device,
-      &mut encoder,
-      &bar.texture_view,
-      &surface.create_view(&wgpu::TextureViewDescriptor::default()),
-    );
-
-    // submit will accept anything that implements IntoIter
-    queue.submit(std::iter::once(encoder.finish()));
+  /// Draws the frame into `output`. On the GPU, only the parts of the bar in
+  /// `damage` are drawn again, and the rest is kept from earlier frames.
+  pub fn render(&mut self, device: Device, output: Target, damage: &[Damage]) {
+    let store = &mut *self.store;
+    let bar = store.bars.get_mut(&self.bar).unwrap();
+
+    match (&mut bar.target, &self.canvas, device, output) {
+      (BarTarget::Cpu(target), Canvas::Cpu(scene), _, Target::Pixels(pixels)) => {
+        target.render(scene, pixels);
+      }
+      (
+        BarTarget::Gpu(target),
+        Canvas::Gpu(scene),
+        Device::Gpu(device, queue),
+        Target::Texture(surface),
+      ) => {
+        let Some(renderer) = &mut store.render else {
+          // Creating the renderer failed, which was logged already.
+          return;
+        };
+        let GpuTarget { blitter, copier, texture, texture_view, output, output_view } = &**target;
+
+        let size = Rect::new(0.0, 0.0, f64::from(output.width()), f64::from(output.height()));
+        let damage = damage
+          .iter()
+          .map(|d| {
+            Rect::new(
+              f64::from(d.x),
+              f64::from(d.y),
+              f64::from(d.x + d.width),
+              f64::from(d.y + d.height),
+            )
+            .intersect(size)
+          })
+          .filter(|rect| rect.area() > 0.0)
+          .collect::<Vec<_>>();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        if !damage.is_empty() {
+          // Everything outside of the damage is clipped out, so vello skips
+          // those tiles.
+          let mut clip = BezPath::new();
+          for rect in &damage {
+            clip.extend(rect.path_elements(0.1));
+          }
+          let mut clipped = Scene::new();
+          clipped.push_clip_layer(Affine::IDENTITY, &clip);
+          clipped.append(scene, None);
+          clipped.pop_layer();
+
+          let res = renderer.render_to_texture(
+            device,
+            queue,
+            &clipped,
+            texture_view,
+            &RenderParams {
+              base_color:          encode_color(Color::BLACK.with_alpha(0.0)),
+              width:               texture.width(),
+              height:              texture.height(),
+              antialiasing_method: vello::AaConfig::Msaa16,
+            },
+          );
+          // The frame is still presented, with whatever the texture had before.
+          match res {
+            Ok(()) => store.render_error = false,
+            Err(e) if !store.render_error => {
+              eprintln!("cannot render bar: {e}");
+              store.render_error = true;
+            }
+            Err(_) => {}
+          }
+
+          blitter.copy(device, &mut encoder, texture_view, output_view, &damage);
+        }
+
+        // The surface doesn't keep its contents between frames, so the whole
+        // bar is copied to it.
+        copier.copy(
+          device,
+          &mut encoder,
+          output_view,
+          &surface.create_view(&wgpu::TextureViewDescriptor::default()),
+        );
+
+        // submit will accept anything that implements IntoIter
+        queue.submit(std::iter::once(encoder.finish()));
+      }
+      // The device changed since the bar was created, which only happens
+      // between frames, so this frame is skipped instead.
+      _ if !store.render_error => {
+        eprintln!("cannot render bar: drawn with a different renderer than it was created with");
+        store.render_error = true;
+      }
+      _ => {}
+    }
   }
 }
@@ -1,21 +1,41 @@
-use cb_core::{oklch, Color};
+fn main() {
+  let config = cb_bar::Config::new(cb_builtin::registry(), include_str!("default.toml"));
 
-const GRAY: Color = Color::new([0.5, 0.0, 0.0, 1.0]);
+  let mut args = std::env::args().skip(1);
+  match args.next().as_deref() {
+    // Renders the bar into a PNG instead of showing it, for previewing themes.
+    #[cfg(feature = "screenshot")]
+    Some("--screenshot") => {
+      let path = args.next().unwrap_or_else(|| "correct-bar.png".into());
+      screenshot(&config, &path);
+    }
+    _ => cb_bar::run(config),
+  }
+}
 
-fn main() {
-  cb_bar::run(cb_bar::Config {
-    make_bar: || cb_bar::Bar {
-      left_modules:   vec![
-        cb_builtin::Hypr { primary: oklch(0.7, 0.2, 310.0), secondary: GRAY }.into()
-      ],
-      center_modules: vec![],
-      right_modules:  vec![
-        cb_builtin::Pulse { primary: oklch(0.7, 0.2, 310.0), secondary: GRAY }.into(),
-        cb_builtin::Temp { primary: oklch(0.7, 0.2, 310.0), secondary: GRAY }.into(),
-        cb_builtin::Cpu { primary: oklch(0.7, 0.17, 20.0), secondary: GRAY }.into(),
-        cb_builtin::Mem { primary: oklch(0.7, 0.19, 140.0), secondary: GRAY }.into(),
-        cb_builtin::Clock { primary: Color::WHITE, secondary: GRAY }.into(),
-      ],
-    },
-  });
+#[cfg(feature = "screenshot")]
+fn screenshot(config: &cb_bar::Config, path: &str) {
+  let (bar, geometry) = match config.build_bar(&cb_core::OutputInfo::default()) {
+    Ok(Some(bar)) => bar,
+    Ok(None) => {
+      eprintln!("no bar is configured for the default output");
+      std::process::exit(1);
+    }
+    Err(e) => {
+      eprintln!("{e}");
+      std::process::exit(1);
+    }
+  };
+
+  let axis = cb_bar::Axis::from(geometry.edge);
+  let (width, height) = match axis {
+    cb_bar::Axis::Horizontal => (1920, geometry.size),
+    cb_bar::Axis::Vertical => (geometry.size, 1080),
+  };
+
+  let image = cb_bar::screenshot(bar, axis, 1.0, width, height);
+  if let Err(e) = image.save(path) {
+    eprintln!("cannot write {path}: {e}");
+    std::process::exit(1);
+  }
 }